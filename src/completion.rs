@@ -0,0 +1,91 @@
+use parser::expected;
+use reader::read;
+use reader::Reader;
+use reader::Token;
+use reader::TokenId;
+use std::rc::Rc;
+use symbols::Symbol;
+use symbols::SymbolTable;
+
+#[derive(Clone, Debug)]
+pub struct Completion {
+    pub id: TokenId,
+    pub name: String,
+    // the tags of the rules expecting the token, outermost first, one list per way to read it
+    pub contexts: Vec<Vec<String>>,
+}
+
+// the candidates which may follow the prefix read by `ongoing`, `name` gives the name of a token id
+pub fn completions<Tk: Token>(ongoing: &Rc<dyn Reader<Tk>>, candidates: &[Tk], table: &SymbolTable, name: impl Fn(TokenId) -> Symbol) -> Vec<Completion> {
+    let mut completions: Vec<Completion> = expected(ongoing, candidates).into_iter()
+        .map(|id| Completion { id, name: table.val(name(id)), contexts: Vec::new() })
+        .collect();
+    let mut contexts = Vec::new();
+    enclosing_tags(ongoing, candidates, &mut Vec::new(), &mut contexts);
+    for (id, context) in contexts {
+        let context: Vec<String> = context.into_iter().map(|sym| table.val(sym)).collect();
+        if let Some(completion) = completions.iter_mut().find(|completion| completion.id == id) {
+            if !completion.contexts.contains(&context) { completion.contexts.push(context); }
+        }
+    }
+    completions
+}
+
+// walks down to the readers of the next token, keeping the tags on the way
+fn enclosing_tags<Tk: Token>(reader: &Rc<dyn Reader<Tk>>, candidates: &[Tk], path: &mut Vec<Symbol>, contexts: &mut Vec<(TokenId, Vec<Symbol>)>) {
+    let tag = reader.tag();
+    // readers sharing the tag of their parent are parts of the same rule
+    let pushed = tag.is_some_and(|tag| path.last() != Some(&tag));
+    if pushed { path.push(tag.unwrap()); }
+    let children = reader.ongoing_children();
+    if children.is_empty() {
+        for tk in candidates {
+            let res = read(reader, *tk);
+            if res.success.is_some() || res.ongoing.is_some() { contexts.push((tk.id(), path.clone())); }
+        }
+    }
+    for child in &children {
+        enclosing_tags(child, candidates, path, contexts);
+    }
+    if pushed { path.pop(); }
+}
+
+#[cfg(test)]
+mod tests {
+    use json::json_grammar;
+    use lexer::tokenize_to_vec;
+    use parser::ParseSession;
+    use super::*;
+
+    fn complete(prefix: &str) -> Vec<Completion> {
+        let mut table = SymbolTable::new();
+        let (lexer, grammar) = json_grammar(&mut table);
+        let mut session = ParseSession::new(&grammar);
+        for token in tokenize_to_vec(&prefix.to_string(), &lexer).unwrap() {
+            session.feed(token.id());
+        }
+        completions(session.ongoing().unwrap(), &lexer.significant_ids(), &table, |id| lexer.rules[id].name)
+    }
+
+    fn names(completions: &[Completion]) -> Vec<&str> {
+        completions.iter().map(|completion| &completion.name[..]).collect()
+    }
+
+    #[test]
+    fn values_may_follow_a_comma() {
+        let completions = complete("[1, ");
+        assert_eq!(names(&completions), vec!["{", "[", "true", "false", "null", "NUMBER", "STRING"]);
+        assert_eq!(completions[0].contexts, vec![vec!["value", "array", "value", "obj"]]);
+        assert_eq!(completions[6].contexts, vec![vec!["value", "array", "value"]]);
+    }
+
+    #[test]
+    fn completions_know_their_rule() {
+        let completions = complete("{\"a\"");
+        assert_eq!(names(&completions), vec![":"]);
+        assert_eq!(completions[0].contexts, vec![vec!["value", "obj", "pair"]]);
+        let completions = complete("[{\"a\": 1");
+        assert_eq!(names(&completions), vec!["}", ","]);
+        assert!(completions.iter().all(|completion| completion.contexts == vec![vec!["value", "array", "value", "obj"]]));
+    }
+}
@@ -0,0 +1,255 @@
+use reader::classes::Classes;
+use reader::TokenId;
+use regex::Regex;
+use std::collections::HashMap;
+
+// the empty language
+fn null() -> Regex {
+    Regex::Class(Vec::new())
+}
+
+fn is_null(regex: &Regex) -> bool {
+    match *regex {
+        Regex::Class(ref bytes) => bytes.is_empty(),
+        _ => false,
+    }
+}
+
+fn nullable(regex: &Regex) -> bool {
+    match *regex {
+        Regex::Empty => true,
+        Regex::Class(_) => false,
+        Regex::Concat(ref elts) => elts.iter().all(nullable),
+        Regex::Alt(ref cases) => cases.iter().any(nullable),
+        Regex::Repeat(ref regex, min, _) => min == 0 || nullable(regex),
+        Regex::Group(ref regex, _) => nullable(regex),
+    }
+}
+
+// the smart constructors keep the regexes normalized, so that equal languages often get equal regexes
+fn concat(elts: Vec<Regex>) -> Regex {
+    let mut flat = Vec::new();
+    for elt in elts {
+        match elt {
+            Regex::Empty => {}
+            Regex::Concat(elts) => flat.extend(elts),
+            elt => {
+                if is_null(&elt) { return null(); }
+                flat.push(elt);
+            }
+        }
+    }
+    match flat.len() {
+        0 => Regex::Empty,
+        1 => flat.pop().unwrap(),
+        _ => Regex::Concat(flat),
+    }
+}
+
+fn alt(cases: Vec<Regex>) -> Regex {
+    let mut flat = Vec::new();
+    let mut bytes = Vec::new();
+    for case in cases {
+        match case {
+            Regex::Alt(cases) => flat.extend(cases),
+            Regex::Class(class) => bytes.extend(class),
+            case => flat.push(case),
+        }
+    }
+    if !bytes.is_empty() {
+        bytes.sort();
+        bytes.dedup();
+        flat.push(Regex::Class(bytes));
+    }
+    flat.sort();
+    flat.dedup();
+    match flat.len() {
+        0 => null(),
+        1 => flat.pop().unwrap(),
+        _ => Regex::Alt(flat),
+    }
+}
+
+fn repeat(regex: Regex, min: usize, max: Option<usize>) -> Regex {
+    if max == Some(0) || regex == Regex::Empty { return Regex::Empty; }
+    if is_null(&regex) { return if min == 0 { Regex::Empty } else { null() }; }
+    if min == 1 && max == Some(1) { return regex; }
+    Regex::Repeat(Box::new(regex), min, max)
+}
+
+// groups only matter to the trees, not to the languages
+fn normalize(regex: &Regex) -> Regex {
+    match *regex {
+        Regex::Concat(ref elts) => concat(elts.iter().map(normalize).collect()),
+        Regex::Alt(ref cases) => alt(cases.iter().map(normalize).collect()),
+        Regex::Repeat(ref regex, min, max) => repeat(normalize(regex), min, max),
+        Regex::Group(ref regex, _) => normalize(regex),
+        ref regex => regex.clone(),
+    }
+}
+
+// the Brzozowski derivative: the words w such that `byte` w matches `regex`
+fn derive(regex: &Regex, byte: u8) -> Regex {
+    match *regex {
+        Regex::Empty => null(),
+        Regex::Class(ref bytes) => if bytes.binary_search(&byte).is_ok() { Regex::Empty } else { null() },
+        Regex::Concat(ref elts) => {
+            let rest = concat(elts[1..].to_vec());
+            let first = concat(vec![derive(&elts[0], byte), rest.clone()]);
+            if nullable(&elts[0]) { alt(vec![first, derive(&rest, byte)]) } else { first }
+        }
+        Regex::Alt(ref cases) => alt(cases.iter().map(|case| derive(case, byte)).collect()),
+        Regex::Repeat(ref regex, min, max) => concat(vec![
+            derive(regex, byte),
+            repeat((**regex).clone(), min.saturating_sub(1), max.map(|max| max - 1)),
+        ]),
+        Regex::Group(ref regex, _) => derive(regex, byte),
+    }
+}
+
+fn class_sets(regex: &Regex, sets: &mut Vec<Vec<TokenId>>) {
+    match *regex {
+        Regex::Empty => {}
+        Regex::Class(ref bytes) => sets.push(bytes.iter().map(|byte| *byte as TokenId).collect()),
+        Regex::Concat(ref regexes) | Regex::Alt(ref regexes) => for regex in regexes { class_sets(regex, sets); },
+        Regex::Repeat(ref regex, _, _) | Regex::Group(ref regex, _) => class_sets(regex, sets),
+    }
+}
+
+const DFA_MAX_STATES: usize = 4096;
+
+// a minimized automaton matching several regexes at once, the first ones winning on equal matches
+pub struct Dfa {
+    // the bytes of a class have the same transitions
+    classes: Classes,
+    transitions: Vec<Vec<usize>>,
+    // the first regex matched by the bytes read up to the state
+    accepts: Vec<Option<usize>>,
+    start: usize,
+    dead: usize,
+}
+
+impl Dfa {
+    // None when the automaton would need too many states
+    pub fn new(regexes: &[Regex]) -> Option<Dfa> {
+        let dead: Vec<Regex> = regexes.iter().map(|_| null()).collect();
+        let start: Vec<Regex> = regexes.iter().map(normalize).collect();
+        let mut sets = Vec::new();
+        for regex in &start { class_sets(regex, &mut sets); }
+        let classes = Classes::new(&sets);
+        let representatives = classes.representatives();
+        let mut ids: HashMap<Vec<Regex>, usize> = HashMap::new();
+        let mut states = vec![dead.clone(), start.clone()];
+        ids.insert(dead.clone(), 0);
+        let start = *ids.entry(start).or_insert(1);
+        if start == 0 { states.pop(); }
+        let mut transitions = Vec::new();
+        let mut accepts = Vec::new();
+        let mut i = 0;
+        while i < states.len() {
+            let mut row = Vec::with_capacity(representatives.len());
+            for id in &representatives {
+                // past the bytes, a class is only read by the dead state
+                let next: Vec<Regex> = if *id < 256 {
+                    states[i].iter().map(|regex| derive(regex, *id as u8)).collect()
+                } else {
+                    dead.clone()
+                };
+                row.push(match ids.get(&next) {
+                    Some(id) => *id,
+                    None => {
+                        if states.len() == DFA_MAX_STATES { return None; }
+                        ids.insert(next.clone(), states.len());
+                        states.push(next);
+                        states.len() - 1
+                    }
+                });
+            }
+            transitions.push(row);
+            accepts.push(states[i].iter().position(nullable));
+            i += 1;
+        }
+        Some(Dfa { classes, transitions, accepts, start, dead: 0 }.minimized())
+    }
+
+    // Moore's algorithm: states are split by acceptance, then by the blocks of their successors
+    fn minimized(self) -> Dfa {
+        let mut ids = HashMap::new();
+        let mut blocks: Vec<usize> = self.accepts.iter().map(|accept| {
+            let len = ids.len();
+            *ids.entry(*accept).or_insert(len)
+        }).collect();
+        let mut nb_blocks = ids.len();
+        loop {
+            let mut ids = HashMap::new();
+            let refined: Vec<usize> = (0..self.transitions.len()).map(|state| {
+                let signature: Vec<usize> = Some(blocks[state]).into_iter()
+                    .chain(self.transitions[state].iter().map(|next| blocks[*next]))
+                    .collect();
+                let len = ids.len();
+                *ids.entry(signature).or_insert(len)
+            }).collect();
+            blocks = refined;
+            if ids.len() == nb_blocks { break; }
+            nb_blocks = ids.len();
+        }
+        let mut transitions = vec![Vec::new(); nb_blocks];
+        let mut accepts = vec![None; nb_blocks];
+        for state in 0..self.transitions.len() {
+            transitions[blocks[state]] = self.transitions[state].iter().map(|next| blocks[*next]).collect();
+            accepts[blocks[state]] = self.accepts[state];
+        }
+        Dfa { classes: self.classes, transitions, accepts, start: blocks[self.start], dead: blocks[self.dead] }
+    }
+
+    // the longest non-empty match as (length, regex index), and the number of bytes read to find it
+    pub fn longest_match(&self, bytes: impl IntoIterator<Item=u8>) -> (Option<(usize, usize)>, usize) {
+        let mut state = self.start;
+        let mut longest = None;
+        let mut nb_read = 0;
+        for byte in bytes {
+            nb_read += 1;
+            state = self.transitions[state][self.classes.class(byte as TokenId)];
+            if state == self.dead { break; }
+            if let Some(regex) = self.accepts[state] { longest = Some((nb_read, regex)); }
+        }
+        (longest, nb_read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::parse_regex;
+    use symbols::SymbolTable;
+
+    fn dfa(patterns: &[&str]) -> Option<Dfa> {
+        let mut table = SymbolTable::new();
+        let regexes: Vec<Regex> = patterns.iter().map(|pattern| parse_regex(&mut table, pattern).unwrap()).collect();
+        Dfa::new(&regexes)
+    }
+
+    #[test]
+    fn longest_matches_win_then_first_regexes() {
+        let dfa = dfa(&["if", "[a-z]+", "[0-9]+(\\.[0-9]+)?"]).unwrap();
+        assert_eq!(dfa.longest_match(b"iffy".iter().cloned()), (Some((4, 1)), 4));
+        assert_eq!(dfa.longest_match(b"if x".iter().cloned()), (Some((2, 0)), 3));
+        assert_eq!(dfa.longest_match(b"12.x".iter().cloned()), (Some((2, 2)), 4));
+        assert_eq!(dfa.longest_match(b"-1".iter().cloned()), (None, 1));
+    }
+
+    #[test]
+    fn states_are_minimized() {
+        // the states after `a`, `aa`, `aaa`... all accept alike
+        let dfa = dfa(&["a+|b*"]).unwrap();
+        assert_eq!(dfa.transitions.len(), 4);
+    }
+
+    #[test]
+    fn automata_are_bounded() {
+        // the automaton remembers the last 13 bytes
+        assert!(dfa(&["(a|b)*a(a|b){12}"]).is_none());
+        let dfa = dfa(&["(a|b)*a(a|b){4}"]).unwrap();
+        assert_eq!(dfa.longest_match(b"babbbb".iter().cloned()), (Some((6, 0)), 6));
+    }
+}
@@ -0,0 +1,152 @@
+use spans::LineIndex;
+use spans::Span;
+use std::fmt::Write;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn name(&self) -> &'static str {
+        match *self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match *self {
+            Severity::Error => "\x1b[1;31m",
+            Severity::Warning => "\x1b[1;33m",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+    pub primary: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+const GUTTER: &str = "\x1b[1;34m";
+const RESET: &str = "\x1b[0m";
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: String) -> Self {
+        Diagnostic { severity, message, labels: Vec::new(), notes: Vec::new() }
+    }
+
+    pub fn error(message: String) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn with_label(mut self, span: Span, message: String) -> Self {
+        self.labels.push(Label { span, message, primary: true });
+        self
+    }
+
+    pub fn with_secondary_label(mut self, span: Span, message: String) -> Self {
+        self.labels.push(Label { span, message, primary: false });
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    pub fn render(&self, src: &str, index: &LineIndex, colors: bool) -> String {
+        let paint = |color: &str, s: &str| if colors { format!("{}{}{}", color, s, RESET) } else { s.to_string() };
+        let mut labels: Vec<&Label> = self.labels.iter().collect();
+        labels.sort_by_key(|label| (label.span.start, !label.primary));
+        let width = labels.iter()
+            .map(|label| (index.line_col(label.span.start).line + 1).to_string().len())
+            .max().unwrap_or(0);
+        let pad = " ".repeat(width);
+        let mut out = String::new();
+        writeln!(out, "{}{}", paint(self.severity.color(), self.severity.name()), paint("\x1b[1m", &format!(": {}", self.message))).unwrap();
+        if let Some(label) = labels.iter().find(|label| label.primary).or(labels.first()) {
+            writeln!(out, "{}{} {}", pad, paint(GUTTER, "-->"), index.line_col(label.span.start)).unwrap();
+            writeln!(out, "{} {}", pad, paint(GUTTER, "|")).unwrap();
+        }
+        for label in labels {
+            let start = index.line_col(label.span.start);
+            let line = index.line_span(start.line);
+            // spans running past their first line are underlined up to the end of it
+            let stop = index.line_col(label.span.stop.max(label.span.start).min(line.stop));
+            let marker = if label.primary { "^" } else { "-" };
+            let color = if label.primary { self.severity.color() } else { GUTTER };
+            let underline = marker.repeat((stop.col - start.col).max(1));
+            writeln!(out, "{} {} {}", paint(GUTTER, &format!("{:>1$}", start.line + 1, width)), paint(GUTTER, "|"), &src[line.start..line.stop]).unwrap();
+            writeln!(out, "{} {} {}{}", pad, paint(GUTTER, "|"), " ".repeat(start.col),
+                   paint(color, format!("{} {}", underline, label.message).trim_end())).unwrap();
+        }
+        for note in &self.notes {
+            writeln!(out, "{} {} {}", pad, paint(GUTTER, "="), paint("\x1b[1m", &format!("note: {}", note))).unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::NoToken;
+    use parser::ParseError;
+
+    #[test]
+    fn labels_are_underlined_below_their_line() {
+        let src = "[1,\n  2 x]";
+        let diagnostic = Diagnostic::error("unexpected `x`".to_string())
+            .with_label(Span::new(8, 9), "here".to_string())
+            .with_secondary_label(Span::new(0, 1), "opened here".to_string())
+            .with_note("expected `,`".to_string());
+        assert_eq!(diagnostic.render(src, &LineIndex::new(src), false), concat!(
+            "error: unexpected `x`\n",
+            " --> 2:5\n",
+            "  |\n",
+            "1 | [1,\n",
+            "  | - opened here\n",
+            "2 |   2 x]\n",
+            "  |     ^ here\n",
+            "  = note: expected `,`\n"));
+    }
+
+    #[test]
+    fn multiline_spans_are_underlined_to_the_end_of_their_first_line() {
+        let src = "ab\ncd";
+        let rendered = Diagnostic::error("e".to_string()).with_label(Span::new(1, 5), String::new()).render(src, &LineIndex::new(src), false);
+        assert!(rendered.ends_with("1 | ab\n  |  ^\n"), "{}", rendered);
+    }
+
+    #[test]
+    fn colors_are_optional() {
+        let src = "x";
+        let diagnostic = Diagnostic::error("e".to_string()).with_label(Span::new(0, 1), String::new());
+        assert!(!diagnostic.render(src, &LineIndex::new(src), false).contains('\x1b'));
+        assert!(diagnostic.render(src, &LineIndex::new(src), true).contains(Severity::Error.color()));
+    }
+
+    #[test]
+    fn lexer_and_parser_errors() {
+        let src = "[1 @";
+        let index = LineIndex::new(src);
+        let no_token = NoToken { start: 3, stop: 4, lookahead: 0 };
+        assert!(no_token.diagnostic(src).render(src, &index, false).contains("  |    ^ `@` does not start any token\n"));
+        let parse_error = ParseError { at: None, expected: vec![1, 2] };
+        let rendered = parse_error.diagnostic(src, |id| ["a", "b", "c"][id].to_string()).render(src, &index, false);
+        assert!(rendered.starts_with("error: unexpected end of input\n"), "{}", rendered);
+        assert!(rendered.ends_with("= note: expected one of `b`, `c`\n"), "{}", rendered);
+    }
+}
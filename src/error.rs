@@ -0,0 +1,57 @@
+use diagnostics::Diagnostic;
+use incremental::BadEdit;
+use layout::LayoutError;
+use lexer::NoToken;
+use parser::ParseError;
+use reader::TokenId;
+use std::io;
+
+// any error from reading, editing, lexing or parsing an input
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Lex(NoToken),
+    Layout(LayoutError),
+    Parse(ParseError),
+    Edit(BadEdit),
+}
+
+impl Error {
+    // `name` gives the name of a token id
+    pub fn diagnostic(&self, src: &str, name: impl Fn(TokenId) -> String) -> Diagnostic {
+        match *self {
+            Error::Io(ref err) => Diagnostic::error(format!("cannot read the input: {}", err)),
+            Error::Lex(ref no_token) => no_token.diagnostic(src),
+            Error::Layout(ref layout_error) => layout_error.diagnostic(src),
+            Error::Parse(ref parse_error) => parse_error.diagnostic(src, name),
+            Error::Edit(ref bad_edit) => bad_edit.diagnostic(),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<NoToken> for Error {
+    fn from(no_token: NoToken) -> Self {
+        Error::Lex(no_token)
+    }
+}
+
+impl From<LayoutError> for Error {
+    fn from(layout_error: LayoutError) -> Self {
+        match layout_error {
+            LayoutError::NoToken(no_token) => Error::Lex(no_token),
+            layout_error => Error::Layout(layout_error),
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(parse_error: ParseError) -> Self {
+        Error::Parse(parse_error)
+    }
+}
@@ -0,0 +1,262 @@
+use diagnostics::Diagnostic;
+use error::Error;
+use lexer::Lexer;
+use lexer::NoToken;
+use lexer::Token;
+use lexer::tokenize_bytes;
+use parser::parse_error;
+use reader::epsilon;
+use reader::read;
+use reader::Reader;
+use reader::ReadingResult;
+use reader::memoization::Lru;
+use reader::memoization::rc_memo_states;
+use reader::TokenId;
+use spans::Spanned;
+use std::mem::take;
+use std::rc::Rc;
+use trees::Tree;
+use trees::tree_from_trace;
+
+// an edit whose bytes are not a range of characters of the text
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BadEdit {
+    pub start: usize,
+    pub stop: usize,
+}
+
+impl BadEdit {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(format!("cannot edit the bytes from {} to {}: they are not a range of characters of the text", self.start, self.stop))
+    }
+}
+
+// the reads kept by each parse state: those of its token and of the one it was edited from
+const STATE_MEMO_CAPACITY: usize = 2;
+
+// a parsed text, only lexed and parsed again around its edits
+pub struct IncrementalDocument<'l> {
+    lexer: &'l Lexer,
+    reader: Rc<dyn Reader<TokenId>>,
+    text: String,
+    tokens: Vec<Rc<Token>>,
+    no_tokens: Vec<NoToken>,
+    // the parse state after each prefix of the tokens
+    states: Vec<ReadingResult<TokenId>>,
+}
+
+fn shift(offset: usize, delta: isize) -> usize {
+    (offset as isize + delta) as usize
+}
+
+fn shifted(token: &Token, delta: isize) -> Token {
+    let mut token = token.clone();
+    token.start = shift(token.start, delta);
+    token.stop = shift(token.stop, delta);
+    token.leading = token.leading.iter().map(|trivia| shifted(trivia, delta)).collect();
+    token.trailing = token.trailing.iter().map(|trivia| shifted(trivia, delta)).collect();
+    token
+}
+
+fn begin(token: &Token) -> usize {
+    token.leading.first().map_or(token.start, |trivia| trivia.start)
+}
+
+// end of the bytes read to lex the token and its trivia
+fn extent(token: &Token) -> usize {
+    token.leading.iter().chain(token.trailing.iter()).chain(Some(token))
+        .map(|tk| tk.stop + tk.lookahead)
+        .max().unwrap()
+}
+
+fn same_state(s1: &ReadingResult<TokenId>, s2: &ReadingResult<TokenId>) -> bool {
+    let same_ongoing = match (&s1.ongoing, &s2.ongoing) {
+        (&Some(ref o1), &Some(ref o2)) => Rc::ptr_eq(o1, o2),
+        (o1, o2) => o1.is_none() && o2.is_none(),
+    };
+    let same_success = match (&s1.success, &s2.success) {
+        (&Some(ref s1), &Some(ref s2)) => Rc::ptr_eq(s1, s2),
+        (s1, s2) => s1.is_none() && s2.is_none(),
+    };
+    same_ongoing && same_success
+}
+
+impl<'l> IncrementalDocument<'l> {
+    pub fn new(lexer: &'l Lexer, reader: Rc<dyn Reader<TokenId>>, text: &str) -> Self {
+        // the states are memoized, so that reading the same tokens again gives the same states
+        let reader = rc_memo_states(reader, Lru::new(STATE_MEMO_CAPACITY));
+        let states = vec![epsilon(&reader)];
+        let mut doc = IncrementalDocument { lexer, reader, text: String::new(), tokens: Vec::new(), no_tokens: Vec::new(), states };
+        let _ = doc.edit(0, 0, text);
+        doc
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn tokens(&self) -> &[Rc<Token>] {
+        &self.tokens
+    }
+
+    // replaces the bytes from `start` to `stop`
+    pub fn edit(&mut self, start: usize, stop: usize, replacement: &str) -> Result<Tree<Rc<Token>>, Vec<Error>> {
+        if start > stop || !self.text.is_char_boundary(start) || !self.text.is_char_boundary(stop) {
+            return Err(vec![Error::Edit(BadEdit { start, stop })]);
+        }
+        let delta = replacement.len() as isize - (stop - start) as isize;
+        self.text.replace_range(start..stop, replacement);
+        // the lexer may be in another mode at a token boundary, so the whole text is lexed again
+        let single_mode = self.lexer.modes.len() == 1;
+        let mut first = if single_mode {
+            let first_no_token = self.no_tokens.iter().filter(|no_token| no_token.stop + no_token.lookahead >= start)
+                .map(|no_token| no_token.start).min().unwrap_or(start);
+            self.tokens.iter().position(|token| extent(token) >= start || token.start >= first_no_token).unwrap_or(self.tokens.len())
+        } else {
+            0
+        };
+        // skipped trivia are not kept, so lexing starts again before the edit, and from the token
+        // before, which may get new trailing trivia
+        while first > 0 && self.tokens.get(first).is_none_or(|token| begin(token) > start) {
+            first -= 1;
+        }
+        first = first.saturating_sub(1);
+        let restart = if first == 0 { 0 } else { begin(&self.tokens[first]) };
+
+        // lexes from the first token reading the edit, until a token lexed again is an old one
+        let mut new_tokens = Vec::new();
+        let mut new_no_tokens = Vec::new();
+        let mut converged = None;
+        for res_token in tokenize_bytes(&self.text.as_bytes()[restart..], self.lexer).recovering().without_traces().significant() {
+            match res_token {
+                Ok(token) => {
+                    let token = shifted(&token, restart as isize);
+                    if single_mode && token.start >= start + replacement.len() {
+                        let old_start = shift(token.start, -delta);
+                        if let Ok(i) = self.tokens[first..].binary_search_by_key(&old_start, |old| old.start) {
+                            let old = &self.tokens[first + i];
+                            if old.id() == token.id() && shift(old.stop, delta) == token.stop {
+                                converged = Some((first + i, token.stop));
+                            }
+                        }
+                    }
+                    new_tokens.push(Rc::new(token));
+                    if converged.is_some() { break; }
+                }
+                Err(no_token) => new_no_tokens.push(NoToken { start: no_token.start + restart, stop: no_token.stop + restart, ..no_token }),
+            }
+        }
+
+        let old_tokens = take(&mut self.tokens);
+        let old_no_tokens = take(&mut self.no_tokens);
+        self.tokens.extend(old_tokens[..first].iter().cloned());
+        self.tokens.extend(new_tokens.iter().cloned());
+        self.no_tokens.extend(old_no_tokens.iter().filter(|no_token| no_token.start < restart).cloned());
+        match converged {
+            Some((last_old, last_stop)) => {
+                self.tokens.extend(old_tokens[last_old + 1..].iter().map(|token| Rc::new(shifted(token, delta))));
+                self.no_tokens.extend(new_no_tokens.into_iter().filter(|no_token| no_token.start < last_stop));
+                self.no_tokens.extend(old_no_tokens.iter().filter(|no_token| no_token.start >= shift(last_stop, -delta))
+                    .map(|no_token| NoToken { start: shift(no_token.start, delta), stop: shift(no_token.stop, delta), ..*no_token }));
+            }
+            None => self.no_tokens.extend(new_no_tokens),
+        }
+
+        // parses from the state before the first token lexed again, until it is an old state
+        let old_states = take(&mut self.states);
+        self.states.extend(old_states[..first + 1].iter().cloned());
+        let reused = first + new_tokens.len();
+        for pos in first..self.tokens.len() {
+            let state = match self.states[pos].ongoing {
+                Some(ref ongoing) => read(ongoing, self.tokens[pos].id()),
+                None => ReadingResult::none(),
+            };
+            self.states.push(state);
+            if let Some((last_old, _)) = converged {
+                if pos >= reused {
+                    let old_pos = pos - reused + last_old + 1;
+                    if same_state(self.states.last().unwrap(), &old_states[old_pos + 1]) {
+                        self.states.extend(old_states[old_pos + 2..].iter().cloned());
+                        break;
+                    }
+                }
+            }
+        }
+        self.tree()
+    }
+
+    pub fn tree(&self) -> Result<Tree<Rc<Token>>, Vec<Error>> {
+        if !self.no_tokens.is_empty() {
+            return Err(self.no_tokens.iter().cloned().map(Error::Lex).collect());
+        }
+        match self.states.last().unwrap().success {
+            Some(ref success) => Ok(tree_from_trace(self.reader.as_tree_builder(), success, &self.tokens)),
+            None => {
+                let tokens = self.tokens.iter().map(|token| (token.id(), token.span()));
+                Err(parse_error(tokens, &self.reader, &self.lexer.significant_ids()).map(Error::Parse).into_iter().collect())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use json::json_grammar;
+    use spans::Span;
+    use spans::Spanned;
+    use super::*;
+    use symbols::SymbolTable;
+
+    fn tokens(doc: &IncrementalDocument) -> Vec<(usize, usize, TokenId)> {
+        doc.tokens().iter().map(|token| (token.start, token.stop, token.id())).collect()
+    }
+
+    fn tree(doc: &IncrementalDocument) -> String {
+        format!("{:?}", doc.tree().map_err(|errors| errors.len()))
+    }
+
+    #[test]
+    fn edits_are_parsed_like_fresh_texts() {
+        let mut table = SymbolTable::new();
+        let (lexer, json) = json_grammar(&mut table);
+        let mut doc = IncrementalDocument::new(&lexer, json.clone(), "{\"a\": [1, 2], \"b\": null}");
+        for &(start, stop, replacement) in &[(8, 9, "true"), (0, 0, " "), (16, 19, "\"c\""), (7, 8, ""), (7, 7, "[")] {
+            let _ = doc.edit(start, stop, replacement);
+            let fresh = IncrementalDocument::new(&lexer, json.clone(), doc.text());
+            assert_eq!(tokens(&doc), tokens(&fresh), "{}", doc.text());
+            assert_eq!(tree(&doc), tree(&fresh), "{}", doc.text());
+        }
+    }
+
+    #[test]
+    fn edits_keeping_the_tokens_keep_the_states() {
+        let mut table = SymbolTable::new();
+        let (lexer, json) = json_grammar(&mut table);
+        let mut doc = IncrementalDocument::new(&lexer, json, "[1, 2, 3, 4, 5, 6]");
+        let old_states = doc.states.clone();
+        let tree = doc.edit(4, 5, "72").unwrap();
+        // the state after the edited number is the old one, and so are the next ones
+        assert_eq!(doc.states.len(), old_states.len());
+        assert!(doc.states.iter().zip(old_states.iter()).all(|(state, old_state)| same_state(state, old_state)));
+        assert_eq!(tree.span(), Some(Span::new(0, 19)));
+        assert_eq!(doc.tokens()[3].span(), Span::new(4, 6));
+    }
+
+    #[test]
+    fn bad_edits_are_rejected() {
+        let mut table = SymbolTable::new();
+        let (lexer, json) = json_grammar(&mut table);
+        let mut doc = IncrementalDocument::new(&lexer, json, "[\"é\"]");
+        for &(start, stop) in &[(3, 2), (0, 7), (2, 3)] {
+            match doc.edit(start, stop, "") {
+                Err(ref errors) => match errors[..] {
+                    [Error::Edit(bad_edit)] => assert_eq!(bad_edit, BadEdit { start, stop }),
+                    _ => panic!("{:?}", errors),
+                },
+                Ok(_) => panic!("{}..{}", start, stop),
+            }
+        }
+        assert_eq!(doc.text(), "[\"é\"]");
+        assert!(doc.tree().is_ok());
+    }
+}
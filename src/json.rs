@@ -1,5 +1,11 @@
+use error::Error;
 use lexer;
+use lexer::Lexer;
+use lexer::Rule;
+use lexer::tokenize;
+use lexer::tokenize_to_vec;
 use parser;
+use parser::ParseMode;
 use reader::conditional_token_reader::ConditionalTokenReader;
 use reader::epsilon_reader::EpsilonReader;
 use reader::list_reader::ListReader;
@@ -10,19 +16,23 @@ use reader::optional_reader::OptionalReader;
 use reader::rc_reader;
 use reader::Reader;
 use reader::ref_reader::RefReader;
+use regex::regex_reader;
 use reader::switch_reader::SwitchReader;
 use reader::Token;
 use reader::token_reader::TokenReader;
 use reader::TokenId;
+use repair::Edit;
+use repair::EditScript;
+use repair::RepairBudget;
+use repair::repairs;
 use std::collections::HashMap;
 use std::rc::Rc;
-use std::time::Instant;
+use spans::LineIndex;
 use symbols::SymbolTable;
 use symbols::Tag;
 use traces::Policy;
 use trees::Tree;
 use trees::tree_from_trace;
-use reader::switch_reader::MemoSwitchReader;
 
 fn tag(table: &mut SymbolTable, s: &str) -> Tag { Some(table.get(s)) }
 
@@ -38,173 +48,254 @@ fn char_reader2(table: &mut SymbolTable, c: char) -> Rc<dyn Reader<u8>> {
 
 fn str_reader(table: &mut SymbolTable, s: &str) -> Rc<dyn Reader<u8>> {
     let elts = s.chars().map(|c| char_reader(c)).collect();
-    rc_memo_reader(ListReader::new(elts, tag(table, s)), 256)
+    rc_memo_reader(ListReader::new(elts, tag(table, s)))
 }
 
 fn opt_reader<Tk: Token + 'static>(reader: Rc<dyn Reader<Tk>>) -> Rc<dyn Reader<Tk>> {
-//    rc_memo_reader(SwitchReader::new(vec![rc_reader(EpsilonReader), reader], Policy::Longest, None), nb)
+//    rc_memo_reader(SwitchReader::new(vec![rc_reader(EpsilonReader), reader], Policy::Longest, None))
     rc_reader(OptionalReader::new(reader))
 }
 
-fn token_reader(token: Rc<dyn Reader<u8>>, token_ids: &HashMap<*const dyn Reader<u8>, TokenId>) -> Rc<dyn Reader<&'static lexer::Token>> {
+fn token_reader(token: Rc<dyn Reader<u8>>, token_ids: &HashMap<*const dyn Reader<u8>, TokenId>) -> Rc<dyn Reader<TokenId>> {
     rc_reader(TokenReader { token_ref: token_ids[&Rc::into_raw(token)], tag: None })
 //    rc_reader(ConditionalTokenReader::include(vec![token_ids[&Rc::into_raw(token)] as u8], 256, None))
 }
 
-fn json_grammar(table: &mut SymbolTable) -> (Rc<dyn Reader<u8>>, Rc<dyn Reader<&'static lexer::Token>>) {
-    let LEFT_BRACE = char_reader2(table, '{');
-    let RIGHT_BRACE = char_reader2(table, '}');
-    let COMMA = char_reader2(table, ',');
-    let COLON = char_reader2(table, ':');
-    let LEFT_BRACKET = char_reader2(table, '[');
-    let RIGHT_BRACKET = char_reader2(table, ']');
-    let DOUBLE_QUOTE = char_reader('"');
-    let MINUS = char_reader('-');
-    let DOT = char_reader('.');
-    let BACKSLASH = char_reader('\\');
-    let TRUE = str_reader(table, "true");
-    let FALSE = str_reader(table, "false");
-    let NULL = str_reader(table, "null");
-    let WS = rc_reader(ConditionalTokenReader::include(" \t\n\r".as_bytes().to_vec(), 256, tag(table, "WS")));
-    let DIGIT = rc_reader(ConditionalTokenReader::include("0123456789".as_bytes().to_vec(), 256, None));
-    let INT = rc_reader(ListReader::new(vec![
-        DIGIT.clone(),
-        rc_reader(LoopReader::new(DIGIT.clone(), Policy::Longest, LoopOrdering::Increasing, None)),
-    ], None));
-    let EXP = rc_reader(ListReader::new(vec![
-        rc_reader(ConditionalTokenReader::include("eE".as_bytes().to_vec(), 256, None)),
-        opt_reader(rc_reader(ConditionalTokenReader::include("+-".as_bytes().to_vec(), 256, None))),
-        INT.clone(),
-    ], None));
-    let NUMBER = rc_reader(ListReader::new(vec![
-        opt_reader(MINUS),
-        INT.clone(),
-        opt_reader(rc_reader(ListReader::new(vec![
-            DOT,
-            INT.clone(),
-        ], None))),
-        opt_reader(EXP),
-    ], tag(table, "NUMBER")));
-    let HEX = rc_reader(ConditionalTokenReader::include("0123456789ABCDEFabcdef".as_bytes().to_vec(), 256, None));
-    let UNICODE = rc_reader(ListReader::new(vec![char_reader('u'), HEX.clone(), HEX.clone(), HEX.clone(), HEX.clone()], None));
-    let ESC = rc_reader(ListReader::new(vec![
-        BACKSLASH,
+pub fn json_grammar(table: &mut SymbolTable) -> (Lexer, Rc<dyn Reader<TokenId>>) {
+    json_grammar_with(table, false)
+}
+
+// with `comments`, `//` comments are lexed as hidden tokens, kept as trivia of their neighbours
+pub fn json_grammar_with(table: &mut SymbolTable, comments: bool) -> (Lexer, Rc<dyn Reader<TokenId>>) {
+    let left_brace = char_reader2(table, '{');
+    let right_brace = char_reader2(table, '}');
+    let comma = char_reader2(table, ',');
+    let colon = char_reader2(table, ':');
+    let left_bracket = char_reader2(table, '[');
+    let right_bracket = char_reader2(table, ']');
+    let double_quote = char_reader('"');
+    let backslash = char_reader('\\');
+    let true_ = str_reader(table, "true");
+    let false_ = str_reader(table, "false");
+    let null = str_reader(table, "null");
+    let ws = rc_reader(ConditionalTokenReader::include(" \t\n\r".as_bytes().to_vec(), 256, tag(table, "WS")));
+    let comment = rc_reader(ListReader::new(vec![
+        char_reader('/'),
+        char_reader('/'),
+        rc_reader(LoopReader::new(
+            rc_reader(ConditionalTokenReader::exclude("\n".as_bytes().to_vec(), 256, None)),
+            Policy::Longest, LoopOrdering::Increasing, None,
+        )),
+    ], tag(table, "COMMENT")));
+    let number_tag = tag(table, "NUMBER");
+    let number = regex_reader(table, "-?(?<int>[0-9]+)(?<frac>\\.[0-9]+)?(?<exp>[eE][+-]?[0-9]+)?", number_tag).unwrap();
+    let hex = rc_reader(ConditionalTokenReader::include("0123456789ABCDEFabcdef".as_bytes().to_vec(), 256, None));
+    let unicode = rc_reader(ListReader::new(vec![char_reader('u'), hex.clone(), hex.clone(), hex.clone(), hex.clone()], None));
+    let esc = rc_reader(ListReader::new(vec![
+        backslash,
         rc_reader(SwitchReader::new(vec![
             rc_reader(ConditionalTokenReader::include("\"\\nt".as_bytes().to_vec(), 256, None)),
-        UNICODE,
+        unicode,
         ], Policy::Longest, None)),
-    ], None));
-    let STRING = rc_reader(ListReader::new(vec![
-        DOUBLE_QUOTE.clone(),
+    ], tag(table, "escape")));
+    let string = rc_reader(ListReader::new(vec![
+        double_quote.clone(),
         rc_reader(LoopReader::new(rc_reader(SwitchReader::new(vec![
-            ESC,
+            esc,
             rc_reader(ConditionalTokenReader::exclude("\\\"".as_bytes().to_vec(), 256, None)),
         ], Policy::Longest, None)), Policy::Longest, LoopOrdering::Increasing, None)),
-        DOUBLE_QUOTE.clone(),
+        double_quote.clone(),
     ], tag(table, "STRING")));
 
-    let tokens = vec![
-        LEFT_BRACE.clone(),
-        RIGHT_BRACE.clone(),
-        COMMA.clone(),
-        COLON.clone(),
-        LEFT_BRACKET.clone(),
-        RIGHT_BRACKET.clone(),
-        TRUE.clone(),
-        FALSE.clone(),
-        NULL.clone(),
-        WS.clone(),
-        NUMBER.clone(),
-        STRING.clone(),
+    let mut tokens = vec![
+        left_brace.clone(),
+        right_brace.clone(),
+        comma.clone(),
+        colon.clone(),
+        left_bracket.clone(),
+        right_bracket.clone(),
+        true_.clone(),
+        false_.clone(),
+        null.clone(),
+        ws.clone(),
+        number.clone(),
+        string.clone(),
     ];
+    if comments { tokens.push(comment.clone()); }
     let token_ids: HashMap<_, _> = tokens.iter().enumerate().map(|p| (Rc::into_raw(p.1.clone()), p.0 as TokenId)).collect();
 
-    let value = rc_reader(RefReader::<&'static lexer::Token>::new());
+    let value = rc_reader(RefReader::<TokenId>::new());
     let array = rc_reader(ListReader::new(vec![
-        token_reader(LEFT_BRACKET.clone(), &token_ids),
+        token_reader(left_bracket.clone(), &token_ids),
         opt_reader(rc_reader(ListReader::new(vec![
             value.clone(),
             rc_reader(LoopReader::new(
                 rc_reader(ListReader::new(vec![
-                    token_reader(COMMA.clone(), &token_ids),
+                    token_reader(comma.clone(), &token_ids),
                     value.clone()
                 ], None)),
                 Policy::Longest, LoopOrdering::Increasing, None,
             ))
         ], None))),
-        token_reader(RIGHT_BRACKET.clone(), &token_ids),
+        token_reader(right_bracket.clone(), &token_ids),
     ], tag(table, "array")));
     let pair = rc_reader(ListReader::new(vec![
-        token_reader(STRING.clone(), &token_ids),
-        token_reader(COLON.clone(), &token_ids),
+        token_reader(string.clone(), &token_ids),
+        token_reader(colon.clone(), &token_ids),
         value.clone()
     ], tag(table, "pair")));
     let obj = rc_reader(ListReader::new(vec![
-        token_reader(LEFT_BRACE.clone(), &token_ids),
+        token_reader(left_brace.clone(), &token_ids),
         opt_reader(rc_reader(ListReader::new(vec![
             pair.clone(),
             rc_memo_reader(LoopReader::new(
                 rc_reader(ListReader::new(vec![
-                    token_reader(COMMA.clone(), &token_ids),
+                    token_reader(comma.clone(), &token_ids),
                     pair.clone()
                 ], None)),
                 Policy::Longest, LoopOrdering::Increasing, None,
-            ), tokens.len())
+            ))
         ], None))),
-        token_reader(RIGHT_BRACE.clone(), &token_ids),
+        token_reader(right_brace.clone(), &token_ids),
     ], tag(table, "obj")));
     let value = RefReader::set(value, rc_reader(SwitchReader::new(vec![
-        token_reader(STRING.clone(), &token_ids),
-        token_reader(NUMBER.clone(), &token_ids),
+        token_reader(string.clone(), &token_ids),
+        token_reader(number.clone(), &token_ids),
         obj,
         array,
-        token_reader(TRUE.clone(), &token_ids),
-        token_reader(FALSE.clone(), &token_ids),
-        token_reader(NULL.clone(), &token_ids),
+        token_reader(true_.clone(), &token_ids),
+        token_reader(false_.clone(), &token_ids),
+        token_reader(null.clone(), &token_ids),
     ], Policy::Longest, tag(table, "value"))));
 
-    let lexer = rc_memo_reader(MemoSwitchReader::new(tokens, Policy::Longest, None), 256);
+    let lexer = Lexer::new(tokens.into_iter().map(|token| {
+        let rule = Rule::new(token.clone());
+        if Rc::ptr_eq(&token, &ws) {
+            rule.skipped()
+        } else if Rc::ptr_eq(&token, &comment) {
+            rule.hidden()
+        } else {
+            rule
+        }
+    }).collect());
     let json = value.clone();
     (lexer, json)
 }
 
-pub fn tokenize_to_vec(s: &String, lexer: Rc<dyn Reader<u8>>, table: &mut SymbolTable) -> Result<Vec<Rc<lexer::Token>>, lexer::NoToken> {
-    let ws = table.get("WS");
-    let mut vec = Vec::new();
-    for res_token in lexer::tokenize(s, lexer) {
-        let token = res_token?;
-        if token.name != ws { vec.push(Rc::new(token)) }
-    }
-    Ok(vec)
+pub fn json_repairs(s: &String, table: &mut SymbolTable, budget: &RepairBudget) -> Option<Vec<(EditScript, Vec<String>)>> {
+    let (lxr, prsr) = json_grammar(table);
+    let tokens = tokenize_to_vec(s, &lxr).ok()?;
+    let input: Vec<TokenId> = tokens.iter().map(|tk| tk.id()).collect();
+    let index = LineIndex::new(s);
+    let position = |pos: usize| index.line_col(if pos < tokens.len() { tokens[pos].start } else { s.len() });
+    let scripts = repairs(&prsr, &input, &lxr.significant_ids(), budget).into_iter().map(|script| {
+        let descs = script.edits.iter().map(|edit| match *edit {
+            Edit::Insert(pos, id) => format!("insert `{}` at {}", table.val(lxr.rules[id].name), position(pos)),
+            Edit::Delete(pos, _) => format!("delete `{}` at {}", &s[tokens[pos].start..tokens[pos].stop], position(pos)),
+        }).collect();
+        (script, descs)
+    }).collect();
+    Some(scripts)
 }
 
-pub fn parse_json(s: &String, table: &mut SymbolTable) -> Option<(Vec<Rc<lexer::Token>>, Tree<Rc<lexer::Token>>)> {
-    let (lxr, prsr) = json_grammar(table);
-    println!("{:?}", &table);
-    let lexing_start = Instant::now();
-    println!("LEXING STARTED");
-    let tokens = match tokenize_to_vec(s, lxr, table) {
-        Ok(tks) => tks,
-        Err(no_token) => {
-            println!("No token found at {} - {}", no_token.start, no_token.stop);
-            return None;
-        },
-    };
-    let parsing_start = Instant::now();
-    println!("PARSING STARTED");
-    let res = parser::parse(tokens.iter().map(|tk| unsafe {&*(tk.as_ref() as *const _)}), &prsr);
-    let success = match res.success {
-        Some(s) => s,
-        None => return None,
-    };
-    let tree_building_start = Instant::now();
-    println!("TREE BUILDING STARTED");
-    println!("DONE");
+pub fn parse_json(s: &String, table: &mut SymbolTable) -> Result<(Vec<Rc<lexer::Token>>, Tree<Rc<lexer::Token>>), Vec<Error>> {
+    parse_json_with(s, table, false, false)
+}
+
+// whitespaces are kept as trivia of the tree leaves, see `lexer::lossless_text`
+pub fn parse_json_lossless(s: &String, table: &mut SymbolTable) -> Result<(Vec<Rc<lexer::Token>>, Tree<Rc<lexer::Token>>), Vec<Error>> {
+    parse_json_with(s, table, false, true)
+}
+
+// see `json_grammar_with` for `comments`
+pub fn parse_json_with(s: &String, table: &mut SymbolTable, comments: bool, lossless: bool) -> Result<(Vec<Rc<lexer::Token>>, Tree<Rc<lexer::Token>>), Vec<Error>> {
+    let (lxr, prsr) = json_grammar_with(table, comments);
+    let tokens = tokenize(s, &lxr).recovering().without_traces();
+    let tokens = if lossless { tokens.lossless() } else { tokens.significant() };
+    let (tokens, success) = parser::parse_lexed(tokens, &prsr, &lxr.significant_ids(), ParseMode::Complete).map_err(|(_, errors)| errors)?;
     let tree = tree_from_trace(prsr.as_tree_builder(), &success, &tokens);
-    let time = Instant::now();
-    println!("lexing = {:?}", parsing_start.duration_since(lexing_start));
-    println!("parsing = {:?}", tree_building_start.duration_since(parsing_start));
-    println!("tree building = {:?}", time.duration_since(tree_building_start));
-    println!("time = {:?}", time.duration_since(lexing_start));
-    Some((tokens, tree))
-}
\ No newline at end of file
+    Ok((tokens, tree))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::lossless_text;
+    use std::time::Instant;
+
+    #[test]
+    fn lexer_rules_do_not_conflict() {
+        for comments in [false, true] {
+            let (lexer, _) = json_grammar_with(&mut SymbolTable::new(), comments);
+            assert!(lexer.conflicts().is_empty());
+            assert!(lexer.overflows().is_empty());
+        }
+    }
+
+    #[test]
+    fn lexer_and_parser_errors_are_reported_alike() {
+        let errors = parse_json(&"[1, @ 2]".to_string(), &mut SymbolTable::new()).err().unwrap();
+        assert!(matches!(errors[..], [Error::Lex(ref no_token)] if no_token.start == 4), "{:?}", errors);
+        let errors = parse_json(&"[1, 2,]".to_string(), &mut SymbolTable::new()).err().unwrap();
+        assert!(matches!(errors[..], [Error::Parse(_)]), "{:?}", errors);
+    }
+
+    #[test]
+    fn trailing_values_are_rejected() {
+        let mut table = SymbolTable::new();
+        let src = "[1] 2".to_string();
+        let errors = parse_json(&src, &mut table).err().unwrap();
+        let (lexer, _) = json_grammar(&mut table);
+        let rendered = errors[0].diagnostic(&src, |id| table.val(lexer.rules[id].name)).render(&src, &LineIndex::new(&src), false);
+        assert!(rendered.starts_with("error: unexpected `2`\n --> 1:5\n"), "{}", rendered);
+        assert!(rendered.ends_with("= note: expected end of input\n"), "{}", rendered);
+    }
+
+    #[test]
+    fn truncated_arrays_are_closed() {
+        let mut table = SymbolTable::new();
+        let src = "[1,\n 2".to_string();
+        let scripts = json_repairs(&src, &mut table, &RepairBudget::default()).unwrap();
+        let (lexer, _) = json_grammar(&mut table);
+        let close = lexer.rules.iter().position(|rule| table.val(rule.name) == "]").unwrap();
+        let (ref script, ref descs) = scripts[0];
+        assert_eq!(script.edits, vec![Edit::Insert(4, close)]);
+        assert_eq!(descs, &vec!["insert `]` at 2:3".to_string()]);
+    }
+
+    #[test]
+    fn comments_are_rejected_by_default() {
+        let src = "[1, // one\n 2]".to_string();
+        let errors = parse_json(&src, &mut SymbolTable::new()).err().unwrap();
+        assert!(errors.iter().any(|err| matches!(*err, Error::Lex(_))));
+    }
+
+    #[test]
+    fn lossless_trees_print_back_their_source() {
+        let src = " {\"a\" :\t[1 ,2]}\n".to_string();
+        let (_, tree) = parse_json_lossless(&src, &mut SymbolTable::new()).unwrap();
+        assert_eq!(lossless_text(&tree, &src), src);
+    }
+
+    #[test]
+    fn comments_are_kept_as_trivia() {
+        let src = "[1, // one\n 2]".to_string();
+        let (tokens, _) = parse_json_with(&src, &mut SymbolTable::new(), true, false).unwrap();
+        assert_eq!(tokens.len(), 5);
+        let comment = &tokens[2].trailing[0];
+        assert_eq!(&src[comment.start..comment.stop], "// one");
+        let (_, tree) = parse_json_with(&src, &mut SymbolTable::new(), true, true).unwrap();
+        assert_eq!(lossless_text(&tree, &src), src);
+    }
+
+    // a benchmark, run with `cargo test --release -- --ignored --nocapture large_documents`
+    #[test]
+    #[ignore]
+    fn large_documents_are_parsed() {
+        let item = "{\"name\": \"item\", \"tags\": [\"a\", \"b\"], \"price\": 12.5e-1, \"stock\": null, \"sold\": true}";
+        let src = format!("[{}]", vec![item; 60_000].join(",\n"));
+        let start = Instant::now();
+        assert!(parse_json(&src, &mut SymbolTable::new()).is_ok());
+        println!("{} bytes parsed in {:?}", src.len(), start.elapsed());
+    }
+}
@@ -0,0 +1,210 @@
+use diagnostics::Diagnostic;
+use lexer::Lexer;
+use lexer::NoToken;
+use lexer::Significant;
+use lexer::Token;
+use lexer::TokenIter;
+use reader::TokenId;
+use sources::Source;
+use spans::Span;
+use std::collections::VecDeque;
+use symbols::Symbol;
+use symbols::SymbolTable;
+
+#[derive(Copy, Clone, Debug)]
+pub struct LayoutToken {
+    pub name: Symbol,
+    pub id: TokenId,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Layout {
+    pub indent: LayoutToken,
+    pub dedent: LayoutToken,
+    pub newline: LayoutToken,
+}
+
+impl Layout {
+    // the synthesized tokens take the ids following the lexer rules
+    pub fn new(lexer: &Lexer, table: &mut SymbolTable) -> Self {
+        let id = lexer.rules.len();
+        Layout {
+            indent: LayoutToken { name: table.get("INDENT"), id },
+            dedent: LayoutToken { name: table.get("DEDENT"), id: id + 1 },
+            newline: LayoutToken { name: table.get("NEWLINE"), id: id + 2 },
+        }
+    }
+
+    // the tokens a parser reads, see `Lexer::significant_ids`
+    pub fn significant_ids(&self, lexer: &Lexer) -> Vec<TokenId> {
+        let mut ids = lexer.significant_ids();
+        ids.extend(vec![self.indent.id, self.dedent.id, self.newline.id]);
+        ids
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum LayoutError {
+    NoToken(NoToken),
+    BadDedent { at: usize, column: usize, expected: Vec<usize> },
+}
+
+impl LayoutError {
+    pub fn diagnostic(&self, src: &str) -> Diagnostic {
+        match *self {
+            LayoutError::NoToken(ref no_token) => no_token.diagnostic(src),
+            LayoutError::BadDedent { at, column, ref expected } => {
+                let expected = expected.iter().map(|col| col.to_string()).collect::<Vec<_>>().join(", ");
+                Diagnostic::error("inconsistent dedent".to_string())
+                    .with_label(Span::new(at, at), format!("indentation of {}", column))
+                    .with_note(format!("expected one of the enclosing indentations: {}", expected))
+            }
+        }
+    }
+}
+
+pub struct LayoutIter<'a, S: Source = &'a [u8]> {
+    tokens: Significant<'a, S>,
+    layout: Layout,
+    indents: Vec<usize>,
+    last_stop: Option<usize>,
+    queue: VecDeque<Result<Token, LayoutError>>,
+}
+
+impl<'a, S: Source> TokenIter<'a, S> {
+    pub fn layout(self, layout: Layout) -> LayoutIter<'a, S> {
+        LayoutIter { tokens: self.significant(), layout, indents: vec![0], last_stop: None, queue: VecDeque::new() }
+    }
+}
+
+impl<'a, S: Source> LayoutIter<'a, S> {
+    fn synthesize(&mut self, token: LayoutToken, at: usize) {
+        self.queue.push_back(Ok(Token::synthetic(token.name, token.id, at)));
+    }
+
+    // `column` is None when the token does not start a line
+    fn process(&mut self, token: Token, column: Option<usize>) {
+        if let Some(column) = column {
+            if let Some(stop) = self.last_stop {
+                let newline = self.layout.newline;
+                self.synthesize(newline, stop);
+            }
+            if column > *self.indents.last().unwrap() {
+                self.indents.push(column);
+                let indent = self.layout.indent;
+                self.synthesize(indent, token.start);
+            }
+            let levels = self.indents.clone();
+            while column < *self.indents.last().unwrap() {
+                self.indents.pop();
+                let dedent = self.layout.dedent;
+                self.synthesize(dedent, token.start);
+            }
+            if column != *self.indents.last().unwrap() {
+                self.queue.push_back(Err(LayoutError::BadDedent { at: token.start, column, expected: levels }));
+                self.indents.push(column);
+            }
+        }
+        self.last_stop = Some(token.stop);
+        self.queue.push_back(Ok(token));
+    }
+
+    fn finish(&mut self) {
+        if let Some(stop) = self.last_stop.take() {
+            let newline = self.layout.newline;
+            self.synthesize(newline, stop);
+            while self.indents.len() > 1 {
+                self.indents.pop();
+                let dedent = self.layout.dedent;
+                self.synthesize(dedent, stop);
+            }
+        }
+    }
+}
+
+impl<'a, S: Source> Iterator for LayoutIter<'a, S> {
+    type Item = Result<Token, LayoutError>;
+
+    fn next(&mut self) -> Option<Result<Token, LayoutError>> {
+        while self.queue.is_empty() {
+            match self.tokens.next_with_column() {
+                Some(Ok((token, column))) => self.process(token, column),
+                Some(Err(no_token)) => self.queue.push_back(Err(LayoutError::NoToken(no_token))),
+                None => {
+                    self.finish();
+                    break;
+                }
+            }
+        }
+        self.queue.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::Rule;
+    use lexer::tokenize;
+    use lexer::tokenize_buf_read;
+    use regex::regex_reader;
+    use std::io::BufReader;
+
+    fn lexer(table: &mut SymbolTable) -> Lexer {
+        let word = Some(table.get("WORD"));
+        let ws = Some(table.get("WS"));
+        Lexer::new(vec![
+            Rule::new(regex_reader(table, "[a-z]+", word).unwrap()),
+            Rule::new(regex_reader(table, "[ \t\n]+", ws).unwrap()).skipped(),
+        ])
+    }
+
+    // W for words, then I, D and N for INDENT, DEDENT and NEWLINE, ! for errors
+    fn kinds(tokens: impl Iterator<Item=Result<Token, LayoutError>>) -> String {
+        tokens.map(|res| match res {
+            Ok(ref token) => ["W", "", "I", "D", "N"][token.id()],
+            Err(_) => "!",
+        }).collect()
+    }
+
+    #[test]
+    fn indentation_is_synthesized() {
+        let mut table = SymbolTable::new();
+        let lexer = lexer(&mut table);
+        let layout = Layout::new(&lexer, &mut table);
+        let src = "a\n  b c\n    d\n  e\nf".to_string();
+        assert_eq!(kinds(tokenize(&src, &lexer).layout(layout)), "WNIWWNIWNDWNDWN");
+        assert_eq!(layout.significant_ids(&lexer), vec![0, 2, 3, 4]);
+    }
+
+    #[test]
+    fn inconsistent_dedents_are_errors() {
+        let mut table = SymbolTable::new();
+        let lexer = lexer(&mut table);
+        let layout = Layout::new(&lexer, &mut table);
+        let src = "a\n    b\n  c".to_string();
+        let results: Vec<_> = tokenize(&src, &lexer).layout(layout).collect();
+        match results[6] {
+            Err(LayoutError::BadDedent { at, column, ref expected }) => assert_eq!((at, column, &expected[..]), (10, 2, &[0, 4][..])),
+            _ => panic!("{:?}", results),
+        }
+    }
+
+    #[test]
+    fn tabs_reach_the_next_tab_stop() {
+        let mut table = SymbolTable::new();
+        let lexer = lexer(&mut table);
+        let layout = Layout::new(&lexer, &mut table);
+        let src = "a\n\tb\n        c\n  \td".to_string();
+        assert_eq!(kinds(tokenize(&src, &lexer).layout(layout)), "WNIWNWNWND");
+    }
+
+    #[test]
+    fn streamed_sources_are_laid_out_alike() {
+        let mut table = SymbolTable::new();
+        let lexer = lexer(&mut table);
+        let layout = Layout::new(&lexer, &mut table);
+        let src = "a\n  b c\n    d\n  e\nf".to_string();
+        let streamed = tokenize_buf_read(BufReader::with_capacity(1, src.as_bytes()), &lexer).layout(layout);
+        assert_eq!(kinds(streamed), kinds(tokenize(&src, &lexer).layout(layout)));
+    }
+}
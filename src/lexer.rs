@@ -1,24 +1,81 @@
+use dfa::Dfa;
+use diagnostics::Diagnostic;
+use diagnostics::Severity;
 use list::List;
 use list::Stack;
 use parser::parse;
 use reader;
+use reader::memoization::Adaptive;
+use reader::memoization::rc_memo_states;
+use reader::epsilon;
+use reader::rc_reader;
+use reader::read;
 use reader::Reader;
+use reader::switch_reader::SwitchReader;
+use regex::Regex;
+use sources::ReadSource;
+use sources::Source;
+use sources::SourceBytes;
+use spans::Span;
+use std::collections::VecDeque;
 use std::fmt::Debug;
 use std::fmt::Error;
 use std::fmt::Formatter;
+use std::io;
+use std::io::BufRead;
+use std::mem::replace;
+use std::mem::take;
 use std::rc::Rc;
 use symbols::Symbol;
+use symbols::SymbolTable;
+use traces::Policy;
 use traces::Trace;
 use traces::TraceEnding;
-use trees::SwitchBuilder;
+use trees::Tree;
+use trees::tree_from_trace_at;
 
 #[derive(Clone)]
 pub struct Token {
     pub name: Symbol,
-    pub traces: Rc<List<Trace, TraceEnding>>,
+    // None when lexed without traces, see `TokenIter::without_traces`
+    pub traces: Option<Rc<List<Trace, TraceEnding>>>,
     pub start: usize,
     pub stop: usize,
+    // bytes read past the token to find its end
+    pub lookahead: usize,
     id: reader::TokenId,
+    pub leading: Vec<Token>,
+    pub trailing: Vec<Token>,
+}
+
+impl Token {
+    pub fn id(&self) -> reader::TokenId {
+        self.id
+    }
+
+    pub fn synthetic(name: Symbol, id: reader::TokenId, at: usize) -> Self {
+        Token { name, traces: None, start: at, stop: at, lookahead: 0, id, leading: Vec::new(), trailing: Vec::new() }
+    }
+
+    // byte-level tree of the token, along its rule
+    pub fn subtree(&self, lexer: &Lexer, src: &[u8]) -> Tree<u8> {
+        let rule = match lexer.rules.get(self.id) {
+            Some(rule) if self.start < self.stop => rule,
+            _ => return Tree::Nil,
+        };
+        let bytes = &src[self.start..self.stop];
+        let traces = match self.traces {
+            Some(ref traces) => traces.clone(),
+            None => parse(bytes.iter().cloned(), &rule.reader).success.unwrap(),
+        };
+        tree_from_trace_at(rule.reader.as_tree_builder(), &traces, bytes, self.start)
+    }
+
+    pub fn write_with_trivia(&self, src: &str, out: &mut String) {
+        for trivia in &self.leading { out.push_str(&src[trivia.start..trivia.stop]); }
+        out.push_str(&src[self.start..self.stop]);
+        for trivia in &self.trailing { out.push_str(&src[trivia.start..trivia.stop]); }
+    }
 }
 
 impl Debug for Token {
@@ -37,50 +94,831 @@ impl<'a> reader::Token for &'a Token {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Normal,
+    // not fed to the parser, but kept as trivia of the neighbouring tokens
+    Hidden,
+    Skip,
+}
+
+pub type ModeId = usize;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Transition {
+    Stay,
+    Push(ModeId),
+    // the first mode stays at the bottom of the stack: popping it is ignored
+    Pop,
+}
+
+pub struct Rule {
+    pub reader: Rc<dyn Reader<u8>>,
+    pub name: Symbol,
+    pub channel: Channel,
+    pub transition: Transition,
+    // wins over lower priorities on equal-length matches, declaration order breaks the remaining ties
+    pub priority: i32,
+}
+
+impl Rule {
+    pub fn new(reader: Rc<dyn Reader<u8>>) -> Self {
+        let name = reader.tag().expect("lexer rules must be tagged");
+        Rule { reader, name, channel: Channel::Normal, transition: Transition::Stay, priority: 0 }
+    }
+
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn pushing(mut self, mode: ModeId) -> Self {
+        self.transition = Transition::Push(mode);
+        self
+    }
+
+    pub fn popping(mut self) -> Self {
+        self.transition = Transition::Pop;
+        self
+    }
+
+    pub fn hidden(mut self) -> Self {
+        self.channel = Channel::Hidden;
+        self
+    }
+
+    pub fn skipped(mut self) -> Self {
+        self.channel = Channel::Skip;
+        self
+    }
+}
+
+pub struct Mode {
+    // token ids of the mode rules, by decreasing priority
+    pub rules: Vec<reader::TokenId>,
+    // the regular rules run as one automaton, indexed by regex
+    dfa: Option<(Dfa, Vec<reader::TokenId>)>,
+    // the other rules run as derivatives, indexed by switch case
+    reader: Option<(Rc<dyn Reader<u8>>, Vec<reader::TokenId>)>,
+    // the regular rules run as derivatives too, their automaton needing too many states
+    overflowed: Vec<reader::TokenId>,
+}
+
+impl Mode {
+    fn new(all_rules: &[Rule], mut rules: Vec<reader::TokenId>) -> Self {
+        // the switch and the automaton keep the first successful rule
+        rules.sort_by_key(|id| -all_rules[*id].priority);
+        let regexes: Vec<Option<Regex>> = rules.iter().map(|id| all_rules[*id].reader.regex()).collect();
+        let regular: Vec<reader::TokenId> = rules.iter().zip(&regexes).filter(|p| p.1.is_some()).map(|p| *p.0).collect();
+        let regexes: Vec<Regex> = regexes.into_iter().flatten().collect();
+        let dfa = if regular.is_empty() { None } else { Dfa::new(&regexes).map(|dfa| (dfa, regular.clone())) };
+        let overflowed = if dfa.is_none() { regular } else { Vec::new() };
+        let others: Vec<reader::TokenId> = match dfa {
+            Some((_, ref regular)) => rules.iter().filter(|id| !regular.contains(id)).cloned().collect(),
+            None => rules.clone(),
+        };
+        let reader = if others.is_empty() {
+            None
+        } else {
+            let cases: Vec<Rc<dyn Reader<u8>>> = others.iter().map(|id| all_rules[*id].reader.clone()).collect();
+            Some((rc_memo_states(rc_reader(SwitchReader::new(cases, Policy::Longest, None)), Adaptive::new()), others))
+        };
+        Mode { rules, dfa, reader, overflowed }
+    }
+
+    fn rank(&self, id: reader::TokenId) -> usize {
+        self.rules.iter().position(|rule| *rule == id).unwrap()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LexerError {
+    NoMode,
+    UnknownRule { mode: ModeId, rule: reader::TokenId },
+    UnknownMode { rule: reader::TokenId, mode: ModeId },
+}
+
+// two rules of a mode with the same priority, which can both match `lexeme`
+#[derive(Clone, Debug)]
+pub struct Conflict {
+    pub mode: ModeId,
+    pub rules: (reader::TokenId, reader::TokenId),
+    pub lexeme: Vec<u8>,
+}
+
+impl Conflict {
+    pub fn diagnostic(&self, lexer: &Lexer, table: &SymbolTable) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, format!(
+            "lexer rules `{}` and `{}` have the same priority and can both match `{}`",
+            table.val(lexer.rules[self.rules.0].name), table.val(lexer.rules[self.rules.1].name),
+            String::from_utf8_lossy(&self.lexeme).escape_debug()))
+    }
+}
+
+// regular rules of a mode which run as derivatives, see `dfa::Dfa::new`
+#[derive(Clone, Debug)]
+pub struct Overflow {
+    pub mode: ModeId,
+    pub rules: Vec<reader::TokenId>,
+}
+
+impl Overflow {
+    pub fn diagnostic(&self, lexer: &Lexer, table: &SymbolTable) -> Diagnostic {
+        let names: Vec<String> = self.rules.iter().map(|id| format!("`{}`", table.val(lexer.rules[*id].name))).collect();
+        Diagnostic::new(Severity::Warning, format!(
+            "the automaton of the lexer rules {} has too many states, they are run as derivatives", names.join(", ")))
+            .with_note(format!("in mode {}", self.mode))
+    }
+}
+
+const CONFLICT_MAX_STATES: usize = 1024;
+
+// breadth-first search of the shortest lexeme matched by both readers, on a bounded number of states
+fn common_lexeme(r1: &Rc<dyn Reader<u8>>, r2: &Rc<dyn Reader<u8>>) -> Option<Vec<u8>> {
+    let mut queue = VecDeque::new();
+    if let (Some(o1), Some(o2)) = (epsilon(r1).ongoing, epsilon(r2).ongoing) {
+        queue.push_back((o1, o2, Vec::new()));
+    }
+    let mut nb_states = 0;
+    while let Some((o1, o2, lexeme)) = queue.pop_front() {
+        for byte in 0..=255u8 {
+            let res1 = read(&o1, byte);
+            if res1.success.is_none() && res1.ongoing.is_none() { continue; }
+            let res2 = read(&o2, byte);
+            let mut next = lexeme.clone();
+            next.push(byte);
+            if res1.success.is_some() && res2.success.is_some() { return Some(next); }
+            if let (Some(n1), Some(n2)) = (res1.ongoing, res2.ongoing) {
+                nb_states += 1;
+                if nb_states > CONFLICT_MAX_STATES { return None; }
+                queue.push_back((n1, n2, next));
+            }
+        }
+    }
+    None
+}
+
+// lexing starts in the first mode; rules are referenced by their index, which is also their token id
+pub struct Lexer {
+    pub rules: Vec<Rule>,
+    pub modes: Vec<Mode>,
+}
+
+impl Lexer {
+    // all the rules in one mode, which none of them can push
+    pub fn new(rules: Vec<Rule>) -> Self {
+        let all = (0..rules.len()).collect();
+        Self::with_modes(rules, vec![all]).expect("rules pushing modes need `Lexer::with_modes`")
+    }
+
+    pub fn with_modes(rules: Vec<Rule>, modes: Vec<Vec<reader::TokenId>>) -> Result<Self, LexerError> {
+        if modes.is_empty() { return Err(LexerError::NoMode); }
+        for (mode, ids) in modes.iter().enumerate() {
+            if let Some(rule) = ids.iter().find(|id| **id >= rules.len()) {
+                return Err(LexerError::UnknownRule { mode, rule: *rule });
+            }
+        }
+        for (rule, r) in rules.iter().enumerate() {
+            if let Transition::Push(mode) = r.transition {
+                if mode >= modes.len() { return Err(LexerError::UnknownMode { rule, mode }); }
+            }
+        }
+        let modes = modes.into_iter().map(|mode| Mode::new(&rules, mode)).collect();
+        Ok(Lexer { rules, modes })
+    }
+
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        let mut conflicts = Vec::new();
+        for (mode_id, mode) in self.modes.iter().enumerate() {
+            for (i, id1) in mode.rules.iter().enumerate() {
+                for id2 in &mode.rules[i + 1..] {
+                    let (rule1, rule2) = (&self.rules[*id1], &self.rules[*id2]);
+                    if rule1.priority != rule2.priority { continue; }
+                    if let Some(lexeme) = common_lexeme(&rule1.reader, &rule2.reader) {
+                        conflicts.push(Conflict { mode: mode_id, rules: (*id1, *id2), lexeme });
+                    }
+                }
+            }
+        }
+        conflicts
+    }
+
+    // the modes lexed slower than their rules allow
+    pub fn overflows(&self) -> Vec<Overflow> {
+        self.modes.iter().enumerate()
+            .filter(|&(_, mode)| !mode.overflowed.is_empty())
+            .map(|(mode, m)| Overflow { mode, rules: m.overflowed.clone() })
+            .collect()
+    }
+
+    // the tokens a parser reads, the other ones being trivia
+    pub fn significant_ids(&self) -> Vec<reader::TokenId> {
+        (0..self.rules.len()).filter(|id| self.rules[*id].channel == Channel::Normal).collect()
+    }
+
+    pub fn channel(&self, id: reader::TokenId) -> Channel {
+        self.rules[id].channel
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct NoToken {
     pub start: usize,
     pub stop: usize,
+    // bytes read past the skipped ones while looking for a token
+    pub lookahead: usize,
+}
+
+impl NoToken {
+    pub fn diagnostic(&self, src: &str) -> Diagnostic {
+        let stop = if self.stop > self.start { self.stop } else { self.start + 1 };
+        let span = Span::new(self.start, stop.min(src.len()));
+        Diagnostic::error("no token found".to_string())
+            .with_label(span, format!("`{}` does not start any token", &src[span.start..span.stop].escape_debug()))
+    }
 }
 
-pub struct TokenIter<'a> {
+pub struct TokenIter<'a, S: Source = &'a [u8]> {
+    source: S,
     bytes_consumed: usize,
-    remaining_bytes: &'a [u8],
-    lexer: Rc<dyn Reader<u8>>,
+    // start of the last yielded token, whose bytes stay buffered for `Significant`
+    last_start: usize,
+    // end of the bytes read since the last token
+    scanned: usize,
+    lexer: &'a Lexer,
+    modes: Vec<ModeId>,
+    recovering: bool,
+    keep_traces: bool,
+    pending: Option<Token>,
+    // lexing ended on an error, when not recovering
+    done: bool,
 }
 
-impl<'a> Iterator for TokenIter<'a> {
+fn char_len(first_byte: u8) -> usize {
+    match first_byte {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => 1,
+    }
+}
+
+impl<'a, S: Source> TokenIter<'a, S> {
+    // keep lexing after a `NoToken`, which then covers every byte skipped before the next token
+    pub fn recovering(mut self) -> Self {
+        self.recovering = true;
+        self
+    }
+
+    // tokens only record their name, id and span, their traces are rebuilt on demand by `Token::subtree`
+    pub fn without_traces(mut self) -> Self {
+        self.keep_traces = false;
+        self
+    }
+
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.source.take_error()
+    }
+
+    pub fn significant(self) -> Significant<'a, S> {
+        Significant { tokens: self, lossless: false, leading: Vec::new(), last: None, trailing_closed: true, column: 0, line_started: true, last_column: None, error: None }
+    }
+
+    // skipped tokens are kept as trivia too, so that the input can be printed back from the tokens
+    pub fn lossless(self) -> Significant<'a, S> {
+        Significant { lossless: true, ..self.significant() }
+    }
+
+    fn mode(&self) -> &'a Mode {
+        &self.lexer.modes[*self.modes.last().unwrap()]
+    }
+
+    fn contains_newline(&self, start: usize, stop: usize) -> bool {
+        let (offset, bytes) = self.source.buffer();
+        bytes[start - offset..stop - offset].contains(&b'\n')
+    }
+
+    // the column after the buffered bytes from `start` to `stop`, and whether they end a line
+    fn column_after(&self, column: usize, start: usize, stop: usize) -> (usize, bool) {
+        let (offset, bytes) = self.source.buffer();
+        let bytes = &bytes[start.max(offset).min(offset + bytes.len()) - offset..stop.max(offset).min(offset + bytes.len()) - offset];
+        bytes.iter().fold((column, false), |(col, newline), b| match *b {
+            b'\n' => (0, true),
+            b'\t' => ((col / TAB_WIDTH + 1) * TAB_WIDTH, newline),
+            _ => (col + 1, newline),
+        })
+    }
+
+    fn token_at(&mut self, skipped: usize) -> Option<Token> {
+        let mode = self.mode();
+        let start = self.bytes_consumed + skipped;
+        // (length, id, traces) of the longest match, the first rule of the mode winning equal lengths
+        let mut longest: Option<(usize, reader::TokenId, Option<Rc<List<Trace, TraceEnding>>>)> = None;
+        let mut nb_read = 0;
+        if let Some((ref dfa, ref regular)) = mode.dfa {
+            let (matched, dfa_read) = dfa.longest_match(SourceBytes::new(&mut self.source, start));
+            nb_read = dfa_read;
+            longest = matched.map(|(len, regex)| (len, regular[regex], None));
+        }
+        if let Some((ref reader, ref others)) = mode.reader {
+            let parsing_res = parse(SourceBytes::new(&mut self.source, start), reader);
+            nb_read = nb_read.max(parsing_res.nb_tokens_read);
+            if let Some(success) = parsing_res.success {
+                // the traces are kept relative to the token rule
+                let (traces, case) = success.pop();
+                let id = match case {
+                    Trace::Switch(case, _) => others[case],
+                    _ => panic!()
+                };
+                let len = parsing_res.success_len;
+                let wins = longest.as_ref().is_none_or(|&(other_len, other, _)| {
+                    len > other_len || len == other_len && mode.rank(id) < mode.rank(other)
+                });
+                if wins { longest = Some((len, id, Some(traces))); }
+            }
+        }
+        self.scanned = self.scanned.max(start + nb_read);
+        let (len, id, traces) = longest?;
+        if len == 0 { return None; }
+        let stop = start + len;
+        let traces = if !self.keep_traces {
+            None
+        } else {
+            // the automaton does not build traces, the rule reads the lexeme again
+            traces.or_else(|| parse(SourceBytes::new(&mut self.source, start).take(len), &self.lexer.rules[id].reader).success)
+        };
+        let name = self.lexer.rules[id].name;
+        let lookahead = start + nb_read - stop;
+        Some(Token { name, traces, start, stop, lookahead, id, leading: Vec::new(), trailing: Vec::new() })
+    }
+
+    fn accept(&mut self, token: Token) -> Option<Result<Token, NoToken>> {
+        self.bytes_consumed = token.stop;
+        self.last_start = token.start;
+        match self.lexer.rules[token.id].transition {
+            Transition::Stay => {}
+            Transition::Push(mode) => self.modes.push(mode),
+            Transition::Pop => if self.modes.len() > 1 { self.modes.pop(); },
+        }
+        Some(Ok(token))
+    }
+
+    fn char_len_at(&mut self, offset: usize) -> Option<usize> {
+        let first_byte = self.source.byte_at(offset)?;
+        let mut len = 1;
+        while len < char_len(first_byte) && self.source.byte_at(offset + len).is_some() {
+            len += 1;
+        }
+        Some(len)
+    }
+}
+
+impl<'a, S: Source> Iterator for TokenIter<'a, S> {
     type Item = Result<Token, NoToken>;
 
     fn next(&mut self) -> Option<Result<Token, NoToken>> {
-        if self.remaining_bytes.len() == 0 { return None; }
-        let parsing_res = parse(self.remaining_bytes.iter().cloned(), &self.lexer);
-        if let Some(success) = parsing_res.success {
-            let id = match success.peek() {
-                Trace::Switch(id, _) => *id,
-                _ => panic!()
-            };
-            let name = match self.lexer.as_tree_builder().switch_builder(id) {
-                SwitchBuilder::Case(case, _) => case.tag().unwrap(),
-                _ => panic!()
-            };
-            let token = Token {
-                name,
-                traces: success,
-                start: self.bytes_consumed,
-                stop: self.bytes_consumed + parsing_res.success_len,
-                id,
+        if self.done { return None; }
+        self.source.release(self.last_start);
+        if let Some(token) = self.pending.take() {
+            return self.accept(token);
+        }
+        let start = self.bytes_consumed;
+        self.last_start = start;
+        self.scanned = start;
+        let mut skipped = self.char_len_at(start)?;
+        if let Some(token) = self.token_at(0) {
+            return self.accept(token);
+        }
+        while self.recovering {
+            let len = match self.char_len_at(start + skipped) {
+                Some(len) => len,
+                None => break,
             };
-            self.bytes_consumed += parsing_res.success_len;
-            self.remaining_bytes = &self.remaining_bytes[parsing_res.success_len..];
-            Some(Ok(token))
-        } else {
-            Some(Err(NoToken { start: self.bytes_consumed, stop: self.bytes_consumed + parsing_res.nb_tokens_read }))
+            self.pending = self.token_at(skipped);
+            if self.pending.is_some() { break; }
+            skipped += len;
         }
+        self.bytes_consumed += skipped;
+        self.done = !self.recovering;
+        let stop = start + skipped;
+        Some(Err(NoToken { start, stop, lookahead: self.scanned.max(stop) - stop }))
     }
 }
 
-pub fn tokenize(s: &String, lexer: Rc<dyn Reader<u8>>) -> TokenIter {
-    TokenIter { bytes_consumed: 0, remaining_bytes: s.as_bytes(), lexer }
+// columns count bytes, tabs moving to the next multiple of `TAB_WIDTH`
+pub const TAB_WIDTH: usize = 8;
+
+// only yields tokens of the normal channel, with the hidden ones as their trivia: a token trails
+// the trivia up to the end of its line, and leads the other ones
+pub struct Significant<'a, S: Source = &'a [u8]> {
+    tokens: TokenIter<'a, S>,
+    lossless: bool,
+    leading: Vec<Token>,
+    last: Option<Token>,
+    trailing_closed: bool,
+    // column after the bytes lexed so far, kept while they are buffered
+    column: usize,
+    // whether a line ended since the last token
+    line_started: bool,
+    last_column: Option<usize>,
+    // an error lexed after `last`, yielded once `last` is
+    error: Option<NoToken>,
 }
 
+impl<'a, S: Source> Significant<'a, S> {
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.tokens.take_error()
+    }
+
+    fn push_trivia(&mut self, trivia: Token) {
+        let keep = self.lossless || self.tokens.lexer.channel(trivia.id) == Channel::Hidden;
+        let ends_line = self.tokens.contains_newline(trivia.start, trivia.stop);
+        match self.last {
+            Some(ref mut last) if !self.trailing_closed => if keep { last.trailing.push(trivia) },
+            _ => if keep { self.leading.push(trivia) },
+        }
+        self.trailing_closed |= ends_line;
+    }
+
+    fn advance(&mut self, start: usize, stop: usize) {
+        let (column, newline) = self.tokens.column_after(self.column, start, stop);
+        self.column = column;
+        self.line_started |= newline;
+    }
+
+    // the next token with its column, when it is the first one of its line
+    pub fn next_with_column(&mut self) -> Option<Result<(Token, Option<usize>), NoToken>> {
+        if let Some(no_token) = self.error.take() {
+            return Some(Err(no_token));
+        }
+        loop {
+            match self.tokens.next() {
+                None => {
+                    let mut last = self.last.take()?;
+                    last.trailing.append(&mut self.leading);
+                    return Some(Ok((last, self.last_column)));
+                }
+                Some(Err(no_token)) => {
+                    self.advance(no_token.start, no_token.stop);
+                    return match self.last.take() {
+                        Some(last) => {
+                            self.error = Some(no_token);
+                            Some(Ok((last, self.last_column)))
+                        }
+                        None => Some(Err(no_token)),
+                    };
+                }
+                Some(Ok(mut token)) => if self.tokens.lexer.channel(token.id()) == Channel::Normal {
+                    let column = if self.line_started { Some(self.column) } else { None };
+                    self.line_started = false;
+                    self.advance(token.start, token.stop);
+                    token.leading = take(&mut self.leading);
+                    self.trailing_closed = false;
+                    let last_column = replace(&mut self.last_column, column);
+                    if let Some(last) = self.last.replace(token) {
+                        return Some(Ok((last, last_column)));
+                    }
+                } else {
+                    self.advance(token.start, token.stop);
+                    self.push_trivia(token);
+                },
+            }
+        }
+    }
+}
+
+impl<'a, S: Source> Iterator for Significant<'a, S> {
+    type Item = Result<Token, NoToken>;
+
+    fn next(&mut self) -> Option<Result<Token, NoToken>> {
+        self.next_with_column().map(|res| res.map(|(token, _)| token))
+    }
+}
+
+pub fn tokenize<'a>(s: &'a String, lexer: &'a Lexer) -> TokenIter<'a> {
+    tokenize_bytes(s.as_bytes(), lexer)
+}
+
+// token spans are byte offsets, so a memory-mapped file can be lexed as a slice
+pub fn tokenize_bytes<'a>(bytes: &'a [u8], lexer: &'a Lexer) -> TokenIter<'a> {
+    tokenize_source(bytes, lexer)
+}
+
+// `io::Read` implementors can be wrapped in a `BufReader`, lexed bytes are dropped as the tokens go
+pub fn tokenize_buf_read<'a, R: BufRead>(reader: R, lexer: &'a Lexer) -> TokenIter<'a, ReadSource<R>> {
+    tokenize_source(ReadSource::new(reader), lexer)
+}
+
+fn tokenize_source<'a, S: Source>(source: S, lexer: &'a Lexer) -> TokenIter<'a, S> {
+    TokenIter { source, bytes_consumed: 0, last_start: 0, scanned: 0, lexer, modes: vec![0], recovering: false, keep_traces: true, pending: None, done: false }
+}
+
+fn collect_tokens(tokens: Significant) -> Result<Vec<Rc<Token>>, Vec<NoToken>> {
+    let mut vec = Vec::new();
+    let mut errors = Vec::new();
+    for res_token in tokens {
+        match res_token {
+            Ok(token) => vec.push(Rc::new(token)),
+            Err(no_token) => errors.push(no_token),
+        }
+    }
+    if errors.is_empty() { Ok(vec) } else { Err(errors) }
+}
+
+pub fn tokenize_to_vec(s: &String, lexer: &Lexer) -> Result<Vec<Rc<Token>>, Vec<NoToken>> {
+    collect_tokens(tokenize(s, lexer).recovering().without_traces().significant())
+}
+
+pub fn tokenize_lossless(s: &String, lexer: &Lexer) -> Result<Vec<Rc<Token>>, Vec<NoToken>> {
+    collect_tokens(tokenize(s, lexer).recovering().without_traces().lossless())
+}
+
+// prints back the input of a tree built from lossless tokens
+pub fn lossless_text(tree: &Tree<Rc<Token>>, src: &str) -> String {
+    let mut out = String::new();
+    match *tree {
+        Tree::Leaf(ref token, _, _) => token.write_with_trivia(src, &mut out),
+        _ => for leaf in tree.leaves() {
+            if let Tree::Leaf(ref token, _, _) = *leaf { token.write_with_trivia(src, &mut out); }
+        },
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::json_grammar;
+    use reader::rc_reader;
+    use reader::ref_reader::RefReader;
+    use regex::regex_reader;
+    use spans::LineIndex;
+
+    fn rule(table: &mut SymbolTable, name: &str, pattern: &str) -> Rule {
+        let tag = Some(table.get(name));
+        Rule::new(regex_reader(table, pattern, tag).unwrap())
+    }
+
+    fn words(table: &mut SymbolTable) -> Lexer {
+        Lexer::new(vec![rule(table, "WORD", "[a-z]+"), rule(table, "WS", " +").skipped()])
+    }
+
+    fn spans(tokens: &[Rc<Token>]) -> Vec<(usize, usize)> {
+        tokens.iter().map(|token| (token.start, token.stop)).collect()
+    }
+
+    #[test]
+    fn lexing_stops_at_the_first_error() {
+        let lexer = words(&mut SymbolTable::new());
+        let src = "ab ? cd".to_string();
+        let mut tokens = tokenize(&src, &lexer);
+        assert_eq!(tokens.next().unwrap().unwrap().id(), 0);
+        assert_eq!(tokens.next().unwrap().unwrap().id(), 1);
+        let no_token = tokens.next().unwrap().unwrap_err();
+        assert_eq!((no_token.start, no_token.stop), (3, 4));
+        assert!(tokens.next().is_none());
+    }
+
+    #[test]
+    fn recovering_lexing_reports_every_error() {
+        let lexer = words(&mut SymbolTable::new());
+        let src = "ab ? cd é?! ef".to_string();
+        let results: Vec<_> = tokenize(&src, &lexer).recovering().significant().collect();
+        let errors: Vec<_> = results.iter().filter_map(|res| res.as_ref().err()).map(|err| (err.start, err.stop)).collect();
+        assert_eq!(errors, vec![(3, 4), (8, 12)]);
+        let errors = tokenize_to_vec(&src, &lexer).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[1].diagnostic(&src).labels[0].span, Span::new(8, 12));
+    }
+
+    #[test]
+    fn recovered_tokens_are_kept() {
+        let lexer = words(&mut SymbolTable::new());
+        let src = "ab ?cd".to_string();
+        let tokens: Vec<_> = tokenize(&src, &lexer).recovering().significant().filter_map(|res| res.ok()).map(Rc::new).collect();
+        assert_eq!(spans(&tokens), vec![(0, 2), (4, 6)]);
+    }
+
+    #[test]
+    fn errors_follow_the_token_before_them() {
+        let lexer = words(&mut SymbolTable::new());
+        let src = "ab?cd".to_string();
+        let results: Vec<_> = tokenize(&src, &lexer).recovering().significant()
+            .map(|res| res.map(|token| (token.start, token.stop)).map_err(|err| (err.start, err.stop))).collect();
+        assert_eq!(results, vec![Ok((0, 2)), Err((2, 3)), Ok((3, 5))]);
+        let mut tokens = tokenize(&src, &lexer).significant();
+        assert_eq!(tokens.next().unwrap().unwrap().stop, 2);
+        assert_eq!(tokens.next().unwrap().unwrap_err().start, 2);
+        assert!(tokens.next().is_none());
+    }
+
+    fn commented(table: &mut SymbolTable) -> Lexer {
+        Lexer::new(vec![
+            rule(table, "WORD", "[a-z]+"),
+            rule(table, "WS", "[ \n]+").skipped(),
+            rule(table, "COMMENT", "#[^\n]*").hidden(),
+        ])
+    }
+
+    fn texts<'s>(trivia: &[Token], src: &'s str) -> Vec<&'s str> {
+        trivia.iter().map(|token| &src[token.start..token.stop]).collect()
+    }
+
+    #[test]
+    fn hidden_tokens_trail_up_to_the_end_of_the_line() {
+        let lexer = commented(&mut SymbolTable::new());
+        let src = "ab # c\n# d\ncd #e".to_string();
+        let tokens = tokenize_to_vec(&src, &lexer).unwrap();
+        assert_eq!(spans(&tokens), vec![(0, 2), (11, 13)]);
+        assert_eq!(texts(&tokens[0].trailing, &src), vec!["# c"]);
+        assert_eq!(texts(&tokens[1].leading, &src), vec!["# d"]);
+        assert_eq!(texts(&tokens[1].trailing, &src), vec!["#e"]);
+    }
+
+    #[test]
+    fn lossless_tokens_keep_skipped_ones() {
+        let lexer = commented(&mut SymbolTable::new());
+        let src = "\n ab # c\n# d\ncd \n".to_string();
+        let tokens = tokenize_lossless(&src, &lexer).unwrap();
+        assert_eq!(texts(&tokens[0].leading, &src), vec!["\n "]);
+        assert_eq!(texts(&tokens[0].trailing, &src), vec![" ", "# c", "\n"]);
+        assert_eq!(texts(&tokens[1].leading, &src), vec!["# d", "\n"]);
+        let mut out = String::new();
+        for token in &tokens { token.write_with_trivia(&src, &mut out); }
+        assert_eq!(out, src);
+        let leaf = Tree::Leaf(tokens[1].clone(), None, Span::new(11, 13));
+        assert_eq!(lossless_text(&leaf, &src), "# d\ncd \n");
+    }
+
+    fn quoted(table: &mut SymbolTable) -> Vec<Rule> {
+        vec![
+            rule(table, "WORD", "[a-z]+"),
+            rule(table, "OPEN", "\"").pushing(1),
+            rule(table, "TEXT", "[^\"]+"),
+            rule(table, "CLOSE", "\"").popping(),
+        ]
+    }
+
+    #[test]
+    fn modes_are_pushed_and_popped() {
+        let lexer = Lexer::with_modes(quoted(&mut SymbolTable::new()), vec![vec![0, 1], vec![2, 3]]).unwrap();
+        let src = "ab\"c d\"ef\"".to_string();
+        let ids: Vec<_> = tokenize_to_vec(&src, &lexer).unwrap().iter().map(|token| token.id()).collect();
+        assert_eq!(ids, vec![0, 1, 2, 3, 0, 1]);
+    }
+
+    #[test]
+    fn popping_the_first_mode_is_ignored() {
+        let lexer = Lexer::with_modes(quoted(&mut SymbolTable::new()), vec![vec![0, 3], vec![2]]).unwrap();
+        let src = "ab\"cd".to_string();
+        let ids: Vec<_> = tokenize_to_vec(&src, &lexer).unwrap().iter().map(|token| token.id()).collect();
+        assert_eq!(ids, vec![0, 3, 0]);
+    }
+
+    #[test]
+    fn modes_are_checked() {
+        let mut table = SymbolTable::new();
+        assert_eq!(Lexer::with_modes(quoted(&mut table), vec![]).err(), Some(LexerError::NoMode));
+        assert_eq!(Lexer::with_modes(quoted(&mut table), vec![vec![0, 1, 4]]).err(), Some(LexerError::UnknownRule { mode: 0, rule: 4 }));
+        assert_eq!(Lexer::with_modes(quoted(&mut table), vec![vec![0, 1, 2, 3]]).err(), Some(LexerError::UnknownMode { rule: 1, mode: 1 }));
+    }
+
+    #[test]
+    fn priorities_break_equal_length_ties() {
+        let mut table = SymbolTable::new();
+        let rules = vec![rule(&mut table, "WORD", "[a-z]+"), rule(&mut table, "TRUE", "true")];
+        let lexer = Lexer::new(rules);
+        let conflicts = lexer.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!((conflicts[0].rules, &conflicts[0].lexeme[..]), ((0, 1), &b"true"[..]));
+        let rendered = conflicts[0].diagnostic(&lexer, &table).render("", &LineIndex::new(""), false);
+        assert!(rendered.starts_with("warning: lexer rules `WORD` and `TRUE` have the same priority and can both match `true`"), "{}", rendered);
+
+        let rules = vec![rule(&mut table, "WORD", "[a-z]+"), rule(&mut table, "TRUE", "true").priority(1), rule(&mut table, "WS", " ").skipped()];
+        let lexer = Lexer::new(rules);
+        assert!(lexer.conflicts().is_empty());
+        let src = "true truer".to_string();
+        let ids: Vec<_> = tokenize_to_vec(&src, &lexer).unwrap().iter().map(|token| token.id()).collect();
+        assert_eq!(ids, vec![1, 0]);
+    }
+
+    fn tagged_parts(tree: &Tree<u8>, table: &SymbolTable) -> Vec<(String, Span)> {
+        tree.tagged().map(|part| (table.val(part.tag().unwrap()).to_string(), part.span().unwrap())).collect()
+    }
+
+    #[test]
+    fn subtrees_expose_the_tagged_parts_of_tokens() {
+        let mut table = SymbolTable::new();
+        let (lexer, _) = json_grammar(&mut table);
+        let src = "[-12.5e3, \"a\\n\"]".to_string();
+        let tokens: Vec<_> = tokenize(&src, &lexer).significant().map(|res| res.unwrap()).collect();
+        let number = tokens[1].subtree(&lexer, src.as_bytes());
+        assert_eq!(number.span(), Some(Span::new(1, 8)));
+        assert_eq!(tagged_parts(&number, &table), vec![
+            ("int".to_string(), Span::new(2, 4)), ("frac".to_string(), Span::new(4, 6)), ("exp".to_string(), Span::new(6, 8))]);
+        let string = tokens[3].subtree(&lexer, src.as_bytes());
+        assert_eq!(tagged_parts(&string, &table), vec![("escape".to_string(), Span::new(12, 14))]);
+    }
+
+    #[test]
+    fn subtrees_are_rebuilt_without_traces() {
+        let mut table = SymbolTable::new();
+        let (lexer, _) = json_grammar(&mut table);
+        let src = "{\"a\\u00e9\": -0.25E+1}".to_string();
+        let traced: Vec<_> = tokenize(&src, &lexer).significant().map(|res| res.unwrap()).collect();
+        let untraced: Vec<_> = tokenize(&src, &lexer).without_traces().significant().map(|res| res.unwrap()).collect();
+        assert_eq!(traced.len(), untraced.len());
+        for (traced, untraced) in traced.iter().zip(&untraced) {
+            assert!(traced.traces.is_some() && untraced.traces.is_none());
+            assert_eq!((traced.id(), traced.start, traced.stop), (untraced.id(), untraced.start, untraced.stop));
+            let subtree = traced.subtree(&lexer, src.as_bytes());
+            assert_eq!(subtree.span(), untraced.subtree(&lexer, src.as_bytes()).span());
+            assert_eq!(tagged_parts(&subtree, &table), tagged_parts(&untraced.subtree(&lexer, src.as_bytes()), &table));
+        }
+        assert_eq!(tagged_parts(&untraced[3].subtree(&lexer, src.as_bytes()), &table).len(), 3);
+    }
+
+    struct Failing;
+
+    impl io::Read for Failing {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("read past the error"))
+        }
+    }
+
+    #[test]
+    fn lexing_does_not_read_past_an_error() {
+        let lexer = words(&mut SymbolTable::new());
+        let mut tokens = tokenize_buf_read(io::BufReader::new(io::Read::chain(&b"ab ?c"[..], Failing)), &lexer);
+        assert_eq!(tokens.by_ref().filter(|res| res.is_err()).count(), 1);
+        assert!(tokens.next().is_none());
+        assert!(tokens.take_error().is_none());
+    }
+
+    #[test]
+    fn streamed_tokens_have_global_spans() {
+        let mut table = SymbolTable::new();
+        let (lexer, _) = json_grammar(&mut table);
+        let src = "{\"a\": [1, 2.5, \"bc\"], \"d\": null}".repeat(20);
+        let streamed: Vec<_> = tokenize_buf_read(io::BufReader::with_capacity(7, src.as_bytes()), &lexer)
+            .significant().map(|res| Rc::new(res.unwrap())).collect();
+        assert_eq!(spans(&streamed), spans(&tokenize_to_vec(&src, &lexer).unwrap()));
+    }
+
+    // hides the regex of the rule, which then runs as derivatives
+    fn opaque(rule: &Rule) -> Rule {
+        let reader = RefReader::set(rc_reader(RefReader::new()), rule.reader.clone());
+        Rule { reader, name: rule.name, channel: rule.channel, transition: rule.transition, priority: rule.priority }
+    }
+
+    fn lexed(src: &String, lexer: &Lexer, table: &SymbolTable) -> Vec<(reader::TokenId, usize, usize, Vec<(String, Span)>)> {
+        tokenize(src, lexer).recovering().filter_map(|res| res.ok())
+            .map(|token| (token.id(), token.start, token.stop, tagged_parts(&token.subtree(lexer, src.as_bytes()), table)))
+            .collect()
+    }
+
+    #[test]
+    fn automata_and_derivatives_lex_alike() {
+        let mut table = SymbolTable::new();
+        let (lexer, _) = json_grammar(&mut table);
+        assert!(lexer.modes[0].dfa.is_some() && lexer.modes[0].reader.is_none());
+        let derivatives = Lexer::new(lexer.rules.iter().map(opaque).collect());
+        assert!(derivatives.modes[0].dfa.is_none());
+        let src = "{\"a\\\"\\u00e9\": [-0.5e+3, 12, true, nul, false], \"b\" :null} @ \"unclosed".to_string();
+        let tokens = lexed(&src, &lexer, &table);
+        assert_eq!(tokens, lexed(&src, &derivatives, &table));
+        assert!(tokens.iter().any(|token| token.3.len() == 3));
+    }
+
+    #[test]
+    fn priorities_hold_across_automata_and_derivatives() {
+        let mut table = SymbolTable::new();
+        let src = "true truer".to_string();
+        let rules = vec![rule(&mut table, "WORD", "[a-z]+"), opaque(&rule(&mut table, "TRUE", "true").priority(1)), rule(&mut table, "WS", " ").skipped()];
+        let lexer = Lexer::new(rules);
+        assert!(lexer.modes[0].dfa.is_some() && lexer.modes[0].reader.is_some());
+        let ids: Vec<_> = tokenize_to_vec(&src, &lexer).unwrap().iter().map(|token| token.id()).collect();
+        assert_eq!(ids, vec![1, 0]);
+        let rules = vec![opaque(&rule(&mut table, "WORD", "[a-z]+")), rule(&mut table, "TRUE", "true").priority(1), rule(&mut table, "WS", " ").skipped()];
+        let ids: Vec<_> = tokenize_to_vec(&src, &Lexer::new(rules)).unwrap().iter().map(|token| token.id()).collect();
+        assert_eq!(ids, vec![1, 0]);
+        // equal priorities: the first rule wins
+        let rules = vec![opaque(&rule(&mut table, "TRUE", "true")), rule(&mut table, "WORD", "[a-z]+"), rule(&mut table, "WS", " ").skipped()];
+        let ids: Vec<_> = tokenize_to_vec(&src, &Lexer::new(rules)).unwrap().iter().map(|token| token.id()).collect();
+        assert_eq!(ids, vec![0, 1]);
+    }
+
+    #[test]
+    fn overflowing_automata_fall_back_to_derivatives() {
+        let mut table = SymbolTable::new();
+        let lexer = Lexer::new(vec![rule(&mut table, "WORD", "[c-z]+"), rule(&mut table, "TAIL", "(a|b)*a(a|b){12}").priority(1)]);
+        assert!(lexer.modes[0].dfa.is_none());
+        let overflows = lexer.overflows();
+        assert_eq!((overflows.len(), overflows[0].mode, &overflows[0].rules[..]), (1, 0, &[1, 0][..]));
+        let rendered = overflows[0].diagnostic(&lexer, &table).render("", &LineIndex::new(""), false);
+        assert!(rendered.starts_with("warning: the automaton of the lexer rules `TAIL`, `WORD` has too many states"), "{}", rendered);
+        let src = "babbbbbbbbbbbbc".to_string();
+        let ids: Vec<_> = tokenize_to_vec(&src, &lexer).unwrap().iter().map(|token| (token.id(), token.stop)).collect();
+        assert_eq!(ids, vec![(1, 14), (0, 15)]);
+    }
+}
@@ -2,6 +2,8 @@ extern crate core;
 extern crate itertools;
 
 
+use error::Error;
+use json::json_grammar;
 use json::parse_json;
 use list::*;
 use reader::*;
@@ -9,7 +11,9 @@ use reader::conditional_token_reader::ConditionalTokenReader;
 use reader::list_reader::ListReader;
 use reader::loop_reader::LoopReader;
 use reader::token_reader::TokenReader;
+use spans::LineIndex;
 use std::fs;
+use std::process;
 use std::rc::Rc;
 use std::time::Instant;
 use symbols::Symbol;
@@ -19,10 +23,23 @@ mod list;
 mod reader;
 mod traces;
 mod symbols;
+mod spans;
+mod sources;
 mod trees;
 mod lexer;
+mod layout;
 mod parser;
 mod json;
+mod repair;
+mod diagnostics;
+mod error;
+mod completion;
+mod incremental;
+mod search;
+mod regex;
+mod dfa;
+#[cfg(test)]
+mod test_grammars;
 
 fn main() {
 //    let nil = Rc::new(List::Nil);
@@ -59,17 +76,35 @@ fn main() {
 //    tree = trees::tree_from_trace(r.as_tree_builder(), &res.success.unwrap(), &vec!['b'][..]);
 //    println!("{:?}", &tree);
 ////    let l = rc_reader(ListReader::new(vec![r.clone(), r.clone()], Symbol::new(1)));
-//    let l = rc_memo_reader(LoopReader::new(r.clone(), traces::Policy::Longest, loop_reader::LoopOrdering::Increasing, Symbol::new(1)), 256);
+//    let l = rc_memo_reader(LoopReader::new(r.clone(), traces::Policy::Longest, loop_reader::LoopOrdering::Increasing, Symbol::new(1)));
 //    res = read(&l, 'a');
 //    res = read(&res.ongoing.unwrap(), 'a');
 //    println!("{:?}", &res);
 //    tree = trees::tree_from_trace(l.as_tree_builder(), &res.success.unwrap(), &vec!['a', 'a'][..]);
 //    println!("{:?}", &tree);
     let filename = "Foods.json";
-    let file = fs::read_to_string(filename).expect("Impossible de lire le fichier");
+    let file = match fs::read_to_string(filename) {
+        Ok(file) => file,
+        Err(err) => exit_with(&[Error::from(err)], "", &mut SymbolTable::new()),
+    };
     println!("file size = {}", file.len());
 //    println!("{}", file);
     let mut table = SymbolTable::new();
-    let (_, tree) = parse_json(&file, &mut table).unwrap();
+    let start = Instant::now();
+    let (_, tree) = match parse_json(&file, &mut table) {
+        Ok(parsed) => parsed,
+        Err(errors) => exit_with(&errors, &file, &mut table),
+    };
+    println!("time = {:?}", start.elapsed());
 //    println!("{:?}", &tree);
 }
+
+// renders the errors on stderr, token ids being named after the json lexer rules
+fn exit_with(errors: &[Error], src: &str, table: &mut SymbolTable) -> ! {
+    let (lexer, _) = json_grammar(table);
+    let index = LineIndex::new(src);
+    for error in errors {
+        eprint!("{}", error.diagnostic(src, |id| table.val(lexer.rules[id].name)).render(src, &index, false));
+    }
+    process::exit(1)
+}
@@ -1,8 +1,22 @@
+use diagnostics::Diagnostic;
+use error::Error;
+use lexer::Significant;
+use lexer::Token;
 use list::List;
 use reader;
+use reader::eoi_reader::EOI;
+use reader::eoi_reader::EoiReader;
 use reader::epsilon;
+use reader::list_reader::ListReader;
+use reader::list_reader::element_traces;
+use reader::rc_reader;
 use reader::read;
+use reader::ReadingResult;
 use reader::Reader;
+use reader::TokenId;
+use sources::Source;
+use spans::Span;
+use spans::Spanned;
 use std::rc::Rc;
 use traces::Trace;
 use traces::TraceEnding;
@@ -14,26 +28,332 @@ pub struct ParsingResult {
 }
 
 impl ParsingResult {
-    fn is_complete(&self) -> bool {
+    pub fn is_complete(&self) -> bool {
         self.success_len == self.nb_tokens_read
     }
 }
 
+// `Prefix` accepts the longest successful prefix of the input, `Complete` only the whole input
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParseMode {
+    Prefix,
+    Complete,
+}
+
 pub fn parse<Tk: reader::Token>(tokens: impl IntoIterator<Item=Tk>, reader: &Rc<dyn Reader<Tk>>) -> ParsingResult {
-    let eps = epsilon(reader);
-    let mut reader = eps.ongoing;
-    let mut success = eps.success;
-    let mut success_len = 0;
+    let mut session = ParseSession::new(reader);
     let mut nb_tokens_read = 0;
     for tk in tokens {
         nb_tokens_read += 1;
-        if reader.is_none() { break; }
-        let res = read(reader.as_ref().unwrap(), tk);
+        if session.ongoing().is_none() { break; }
+        session.feed(tk);
+    }
+    let (success, success_len) = session.longest_success();
+    ParsingResult { success: success.cloned(), success_len, nb_tokens_read }
+}
+
+// parse state fed one token at a time, cloning it forks the parse
+#[derive(Clone)]
+pub struct ParseSession<Tk: reader::Token> {
+    ongoing: Option<Rc<dyn Reader<Tk>>>,
+    success: Option<Rc<List<Trace, TraceEnding>>>,
+    longest_success: Option<Rc<List<Trace, TraceEnding>>>,
+    longest_success_len: usize,
+    nb_tokens: usize,
+}
+
+impl<Tk: reader::Token> ParseSession<Tk> {
+    pub fn new(reader: &Rc<dyn Reader<Tk>>) -> Self {
+        let eps = epsilon(reader);
+        ParseSession { ongoing: eps.ongoing, success: eps.success.clone(), longest_success: eps.success, longest_success_len: 0, nb_tokens: 0 }
+    }
+
+    // returns whether the input is still viable
+    pub fn feed(&mut self, tk: Tk) -> bool {
+        self.nb_tokens += 1;
+        let res = match self.ongoing {
+            Some(ref ongoing) => read(ongoing, tk),
+            None => ReadingResult::none(),
+        };
         if res.success.is_some() {
-            success = res.success;
-            success_len = nb_tokens_read;
+            self.longest_success = res.success.clone();
+            self.longest_success_len = self.nb_tokens;
+        }
+        self.success = res.success;
+        self.ongoing = res.ongoing;
+        self.is_viable()
+    }
+
+    // the input fed so far is a complete parse
+    pub fn is_complete(&self) -> bool {
+        self.success.is_some()
+    }
+
+    // the input fed so far is complete or can still be completed
+    pub fn is_viable(&self) -> bool {
+        self.success.is_some() || self.ongoing.is_some()
+    }
+
+    // the trace of the input fed so far, when complete
+    pub fn success(&self) -> Option<&Rc<List<Trace, TraceEnding>>> {
+        self.success.as_ref()
+    }
+
+    // the trace of the longest complete prefix, and its length
+    pub fn longest_success(&self) -> (Option<&Rc<List<Trace, TraceEnding>>>, usize) {
+        (self.longest_success.as_ref(), self.longest_success_len)
+    }
+
+    pub fn ongoing(&self) -> Option<&Rc<dyn Reader<Tk>>> {
+        self.ongoing.as_ref()
+    }
+
+    pub fn nb_tokens(&self) -> usize {
+        self.nb_tokens
+    }
+
+    // the readers are persistent, so forking only shares them
+    pub fn fork(&self) -> Self {
+        self.clone()
+    }
+
+    // feeds each continuation to a fork, and keeps the first best one with its index: complete
+    // parses come first, then viable ones, then the ones with the longest complete prefix
+    pub fn best_continuation<C: IntoIterator<Item=Tk>>(&self, continuations: impl IntoIterator<Item=C>) -> Option<(usize, Self)> {
+        let mut best: Option<(usize, Self)> = None;
+        for (i, continuation) in continuations.into_iter().enumerate() {
+            let mut fork = self.fork();
+            for tk in continuation {
+                if !fork.feed(tk) { break; }
+            }
+            if best.as_ref().is_none_or(|&(_, ref best)| fork.rank() > best.rank()) {
+                best = Some((i, fork));
+            }
         }
-        reader = res.ongoing;
+        best
+    }
+
+    fn rank(&self) -> (bool, bool, usize) {
+        (self.is_complete(), self.is_viable(), self.longest_success_len)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ParseError {
+    // the bytes of the token which cannot be read, None at the end of the input
+    pub at: Option<Span>,
+    pub expected: Vec<TokenId>,
+}
+
+impl ParseError {
+    pub fn diagnostic(&self, src: &str, name: impl Fn(TokenId) -> String) -> Diagnostic {
+        let (span, message) = match self.at {
+            Some(span) => (span, format!("unexpected `{}`", &src[span.start..span.stop])),
+            None => (Span::new(src.len(), src.len()), "unexpected end of input".to_string()),
+        };
+        let note = if self.expected.is_empty() {
+            "expected end of input".to_string()
+        } else {
+            format!("expected one of {}", self.expected.iter().map(|id| format!("`{}`", name(*id))).collect::<Vec<_>>().join(", "))
+        };
+        Diagnostic::error(message).with_label(span, String::new()).with_note(note)
+    }
+}
+
+pub fn expected<Tk: reader::Token>(reader: &Rc<dyn Reader<Tk>>, candidates: &[Tk]) -> Vec<TokenId> {
+    candidates.iter()
+        .filter(|tk| {
+            let res = read(reader, **tk);
+            res.success.is_some() || res.ongoing.is_some()
+        })
+        .map(|tk| tk.id())
+        .collect()
+}
+
+fn expected_after<Tk: reader::Token>(session: &ParseSession<Tk>, candidates: &[Tk]) -> Vec<TokenId> {
+    session.ongoing().map(|ongoing| expected(ongoing, candidates)).unwrap_or_default()
+}
+
+// the reader followed by an end of input anchor, which reads `EOI` once the input is over
+fn anchored(reader: &Rc<dyn Reader<TokenId>>) -> Rc<dyn Reader<TokenId>> {
+    rc_reader(ListReader::new(vec![reader.clone(), rc_reader(EoiReader)], None))
+}
+
+// the trace of the reader, from the one of its anchored reader
+fn unanchored(traces: &Rc<List<Trace, TraceEnding>>) -> Rc<List<Trace, TraceEnding>> {
+    element_traces(traces)[0].clone()
+}
+
+// the trace of the whole input, or an error at the first token which cannot be read or at its end;
+// the tokens come with their bytes
+pub fn parse_complete(tokens: impl IntoIterator<Item=(TokenId, Span)>, reader: &Rc<dyn Reader<TokenId>>, candidates: &[TokenId]) -> Result<Rc<List<Trace, TraceEnding>>, ParseError> {
+    let mut session = ParseSession::new(&anchored(reader));
+    for (tk, span) in tokens {
+        let before = session.fork();
+        if !session.feed(tk) {
+            return Err(ParseError { at: Some(span), expected: expected_after(&before, candidates) });
+        }
+    }
+    let before_end = session.fork();
+    session.feed(EOI);
+    match session.success() {
+        Some(success) => Ok(unanchored(success)),
+        None => Err(ParseError { at: None, expected: expected_after(&before_end, candidates) }),
+    }
+}
+
+pub fn parse_error(tokens: impl IntoIterator<Item=(TokenId, Span)>, reader: &Rc<dyn Reader<TokenId>>, candidates: &[TokenId]) -> Option<ParseError> {
+    parse_complete(tokens, reader, candidates).err()
+}
+
+// parses the tokens as they are lexed, only keeping them to build the tree
+pub fn parse_lexed<S: Source>(mut tokens: Significant<S>, reader: &Rc<dyn Reader<TokenId>>, candidates: &[TokenId], mode: ParseMode) -> Result<(Vec<Rc<Token>>, Rc<List<Trace, TraceEnding>>), (Vec<Rc<Token>>, Vec<Error>)> {
+    let mut read_tokens = Vec::new();
+    let mut errors = Vec::new();
+    let anchored = anchored(reader);
+    let mut session = ParseSession::new(if mode == ParseMode::Complete { &anchored } else { reader });
+    let mut failed = None;
+    for res_token in tokens.by_ref() {
+        match res_token {
+            Ok(token) => {
+                let before = session.fork();
+                session.feed(token.id());
+                let span = token.span();
+                read_tokens.push(Rc::new(token));
+                if !session.is_viable() {
+                    failed = Some(ParseError { at: Some(span), expected: expected_after(&before, candidates) });
+                    break;
+                }
+                if mode == ParseMode::Prefix && session.ongoing().is_none() { break; }
+            }
+            Err(no_token) => errors.push(Error::Lex(no_token)),
+        }
+    }
+    if let Some(err) = tokens.take_error() {
+        errors.push(Error::Io(err));
+    }
+    if !errors.is_empty() { return Err((read_tokens, errors)); }
+    let before_end = session.fork();
+    if mode == ParseMode::Complete { session.feed(EOI); }
+    let success = match mode {
+        ParseMode::Prefix => session.longest_success().0.cloned(),
+        ParseMode::Complete => session.success().map(unanchored),
+    };
+    match success {
+        Some(success) => Ok((read_tokens, success)),
+        None => {
+            let error = failed.unwrap_or_else(|| ParseError { at: None, expected: expected_after(&before_end, candidates) });
+            Err((read_tokens, vec![Error::Parse(error)]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reader::rc_reader;
+    use super::*;
+    use test_grammars::*;
+    use trees::Tree;
+    use trees::tree_from_trace_at;
+
+    #[test]
+    fn sessions_are_fed_one_token_at_a_time() {
+        let grammar = grammar();
+        let mut session = ParseSession::new(&grammar);
+        assert!(!session.is_complete() && session.is_viable());
+        assert!(session.feed(OPEN));
+        assert!(session.feed(A));
+        assert!(!session.is_complete() && session.success().is_none());
+        assert!(session.feed(CLOSE));
+        assert!(session.is_complete() && session.success().is_some());
+        assert!(!session.feed(A));
+        assert!(!session.is_complete() && !session.is_viable());
+        assert_eq!(session.nb_tokens(), 4);
+        let (longest, len) = session.longest_success();
+        assert!(longest.is_some());
+        assert_eq!(len, 3);
+    }
+
+    #[test]
+    fn parse_keeps_the_longest_prefix() {
+        let res = parse(vec![OPEN, A, CLOSE, A, A], &grammar());
+        assert!(res.success.is_some() && !res.is_complete());
+        assert_eq!((res.success_len, res.nb_tokens_read), (3, 4));
+        assert!(parse(vec![OPEN, A, CLOSE], &grammar()).is_complete());
+    }
+
+    #[test]
+    fn forks_are_independent() {
+        let grammar = grammar();
+        let mut session = ParseSession::new(&grammar);
+        session.feed(OPEN);
+        let mut fork = session.fork();
+        fork.feed(CLOSE);
+        assert!(fork.is_complete() && !session.is_complete());
+        assert_eq!((session.nb_tokens(), fork.nb_tokens()), (1, 2));
+        session.feed(A);
+        assert!(session.is_viable() && !session.is_complete());
+    }
+
+    #[test]
+    fn complete_continuations_are_preferred() {
+        let grammar = grammar();
+        let mut session = ParseSession::new(&grammar);
+        session.feed(OPEN);
+        let (i, best) = session.best_continuation(vec![vec![CLOSE, A], vec![A, A], vec![A, CLOSE], vec![CLOSE]]).unwrap();
+        assert_eq!(i, 2);
+        assert!(best.is_complete());
+        let (i, best) = session.best_continuation(vec![vec![OPEN], vec![CLOSE, CLOSE], vec![A]]).unwrap();
+        assert_eq!(i, 2);
+        assert!(best.is_viable());
+        // failed continuations are ranked by their longest complete prefix
+        let (i, _) = session.best_continuation(vec![vec![OPEN], vec![CLOSE, CLOSE]]).unwrap();
+        assert_eq!(i, 1);
+        assert!(session.best_continuation(Vec::<Vec<TokenId>>::new()).is_none());
+    }
+
+    fn spanned(tokens: &[TokenId]) -> Vec<(TokenId, Span)> {
+        tokens.iter().enumerate().map(|(i, tk)| (*tk, Span::new(2 * i, 2 * i + 1))).collect()
+    }
+
+    #[test]
+    fn complete_parses_read_the_whole_input() {
+        let grammar = grammar();
+        let tokens = vec![OPEN, A, A, CLOSE];
+        let trace = parse_complete(spanned(&tokens), &grammar, &[OPEN, CLOSE, A]).unwrap();
+        let tree = tree_from_trace_at(grammar.as_tree_builder(), &trace, &tokens, 0);
+        assert_eq!(tree.leaves().count(), 4);
+        assert_eq!(tree.span(), Some(Span::new(0, 4)));
+    }
+
+    #[test]
+    fn trailing_tokens_are_rejected() {
+        let err = parse_complete(spanned(&[OPEN, CLOSE, A]), &grammar(), &[OPEN, CLOSE, A]).unwrap_err();
+        assert_eq!((err.at, err.expected), (Some(Span::new(4, 5)), vec![]));
+        let err = parse_complete(spanned(&[OPEN, OPEN, A]), &grammar(), &[OPEN, CLOSE, A]).unwrap_err();
+        assert_eq!((err.at, err.expected), (Some(Span::new(2, 3)), vec![CLOSE, A]));
+    }
+
+    #[test]
+    fn incomplete_inputs_fail_at_their_end() {
+        let err = parse_complete(spanned(&[OPEN, A]), &grammar(), &[OPEN, CLOSE, A]).unwrap_err();
+        assert_eq!((err.at, err.expected), (None, vec![CLOSE, A]));
+        let err = parse_complete(spanned(&[]), &grammar(), &[OPEN, CLOSE, A]).unwrap_err();
+        assert_eq!((err.at, err.expected), (None, vec![OPEN]));
+    }
+
+    #[test]
+    fn the_anchor_only_reads_the_end_of_input() {
+        let eoi: Rc<dyn Reader<TokenId>> = rc_reader(EoiReader);
+        assert!(epsilon(&eoi).success.is_none() && epsilon(&eoi).ongoing.is_some());
+        assert!(read(&eoi, A).success.is_none() && read(&eoi, A).ongoing.is_none());
+        // the sentinel is not a token of the input, and builds no tree
+        let success = read(&eoi, EOI).success.unwrap();
+        assert!(matches!(tree_from_trace_at(eoi.as_tree_builder(), &success, &[A], 0), Tree::Nil));
+        let anchored = anchored(&grammar());
+        let mut session = ParseSession::new(&anchored);
+        for tk in [OPEN, CLOSE, EOI] { session.feed(tk); }
+        let tree = tree_from_trace_at(anchored.as_tree_builder(), session.success().unwrap(), &[OPEN, CLOSE], 0);
+        assert_eq!(tree.leaves().count(), 2);
     }
-    ParsingResult { success, success_len, nb_tokens_read }
 }
@@ -0,0 +1,113 @@
+use reader::*;
+use reader::interning::address;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+// a partition of the token ids such that the readers it was computed for read the ids of a class
+// alike, memo tables and automata being indexed by class instead of by id
+#[derive(Clone, Debug)]
+pub struct Classes {
+    of: Vec<usize>,
+    nb_classes: usize,
+}
+
+impl Classes {
+    // the coarsest partition whose classes are each either inside or outside every set, the ids
+    // past those of the sets being in no set, like the first of them
+    pub fn new(sets: &[Vec<TokenId>]) -> Self {
+        let nb_tokens = sets.iter().flat_map(|set| set.iter()).max().map_or(0, |id| id + 1);
+        let mut of = vec![0; nb_tokens + 1];
+        let mut nb_classes = 1;
+        let mut member = vec![false; nb_tokens + 1];
+        for set in sets {
+            for id in set { member[*id] = true; }
+            let mut ids = HashMap::new();
+            for id in 0..of.len() {
+                let len = ids.len();
+                of[id] = *ids.entry((of[id], member[id])).or_insert(len);
+            }
+            nb_classes = ids.len();
+            for id in set { member[*id] = false; }
+        }
+        Classes { of, nb_classes }
+    }
+
+    pub fn class(&self, id: TokenId) -> usize {
+        self.of[id.min(self.of.len() - 1)]
+    }
+
+    pub fn len(&self) -> usize {
+        self.nb_classes
+    }
+
+    // the first id of each class, which may be the first id past those of the sets
+    pub fn representatives(&self) -> Vec<TokenId> {
+        let mut representatives = Vec::with_capacity(self.nb_classes);
+        for id in 0..self.of.len() {
+            if self.of[id] == representatives.len() { representatives.push(id); }
+        }
+        representatives
+    }
+}
+
+// the classes of the tokens read by the readers and all the readers they are built from
+pub fn classes_of<Tk: Token>(readers: &[Rc<dyn Reader<Tk>>]) -> Classes {
+    let mut sets = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack: Vec<Rc<dyn Reader<Tk>>> = readers.to_vec();
+    while let Some(reader) = stack.pop() {
+        if !visited.insert(address(&reader)) { continue; }
+        sets.extend(reader.token_sets());
+        stack.extend(reader.grammar_children());
+    }
+    Classes::new(&sets)
+}
+
+#[cfg(test)]
+mod tests {
+    use reader::conditional_token_reader::ConditionalTokenReader;
+    use reader::list_reader::ListReader;
+    use reader::loop_reader::LoopOrdering;
+    use reader::loop_reader::LoopReader;
+    use reader::ref_reader::RefReader;
+    use reader::switch_reader::SwitchReader;
+    use reader::token_reader::TokenReader;
+    use super::*;
+    use traces::Policy;
+
+    #[test]
+    fn ids_are_split_by_the_sets_they_are_in() {
+        let classes = Classes::new(&[vec![1, 2, 3], vec![3, 4]]);
+        assert_eq!(classes.len(), 4);
+        assert_eq!(classes.class(1), classes.class(2));
+        assert_ne!(classes.class(2), classes.class(3));
+        assert_ne!(classes.class(3), classes.class(4));
+        assert_eq!(classes.representatives(), vec![0, 1, 3, 4]);
+    }
+
+    #[test]
+    fn ids_past_the_sets_are_in_no_set() {
+        let classes = Classes::new(&[vec![1, 2]]);
+        assert_eq!(classes.class(3), classes.class(0));
+        assert_eq!(classes.class(1000), classes.class(0));
+        assert_eq!(Classes::new(&[]).class(7), 0);
+    }
+
+    #[test]
+    fn recursive_grammars_are_visited_once() {
+        let token = |c: char| rc_reader(TokenReader { token_ref: c as TokenId, tag: None }) as Rc<dyn Reader<u8>>;
+        let digit = rc_reader(ConditionalTokenReader::include("0123456789".as_bytes().to_vec(), 256, None));
+        // `value = digit+ | [ value ]`
+        let value = rc_reader(RefReader::<u8>::new());
+        let value = RefReader::set(value.clone(), rc_reader(SwitchReader::new(vec![
+            rc_reader(LoopReader::new(digit, Policy::Longest, LoopOrdering::Increasing, None)),
+            rc_reader(ListReader::new(vec![token('['), value, token(']')], None)),
+        ], Policy::Longest, None)));
+        let classes = classes_of(&[value]);
+        assert_eq!(classes.len(), 4);
+        assert_eq!(classes.class('0' as TokenId), classes.class('9' as TokenId));
+        assert_eq!(classes.class('a' as TokenId), classes.class(' ' as TokenId));
+        assert_ne!(classes.class('[' as TokenId), classes.class(']' as TokenId));
+    }
+}
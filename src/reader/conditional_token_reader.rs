@@ -1,4 +1,6 @@
 use reader::*;
+use regex::group;
+use regex::Regex;
 use std::fmt::Debug;
 use std::fmt::Error;
 use std::fmt::Formatter;
@@ -45,6 +47,16 @@ impl<Tk: Token> Reader<Tk> for ConditionalTokenReader<Tk> {
     fn read(&self, _: &Rc<dyn Reader<Tk>>, token: Tk) -> ReadingResult<Tk> {
         self.matching[token.id()].clone()
     }
+
+    fn token_sets(&self) -> Vec<Vec<TokenId>> {
+        vec![(0..self.matching.len()).filter(|id| self.matching[*id].success.is_some()).collect()]
+    }
+
+    fn regex(&self) -> Option<Regex> {
+        if self.matching.len() > 256 { return None; }
+        let bytes = (0..self.matching.len()).filter(|id| self.matching[*id].success.is_some()).map(|id| id as u8).collect();
+        Some(group(Regex::Class(bytes), self.tag))
+    }
 }
 
 impl<Tk: Token> TreeBuilder for ConditionalTokenReader<Tk> {
@@ -0,0 +1,47 @@
+use reader::*;
+use std::fmt::Debug;
+use std::fmt::Error;
+use std::fmt::Formatter;
+use std::iter::empty;
+use std::rc::Rc;
+use symbols::Tag;
+use traces::epsilon_trace;
+use trees::*;
+
+// the id of the end of input sentinel, fed after the last token, see `parser::parse_complete`
+pub const EOI: TokenId = usize::MAX;
+
+// the end of input anchor: it only reads the sentinel, which is not a token of the input, so that
+// its trace builds no tree
+pub struct EoiReader;
+
+impl Debug for EoiReader {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "$")
+    }
+}
+
+impl<Tk: Token> Reader<Tk> for EoiReader {
+    fn epsilon(&self, this: &Rc<dyn Reader<Tk>>) -> ReadingResult<Tk> {
+        ReadingResult { success: None, ongoing: Some(this.clone()) }
+    }
+
+    fn read(&self, _: &Rc<dyn Reader<Tk>>, token: Tk) -> ReadingResult<Tk> {
+        ReadingResult { success: if token.id() == EOI { Some(epsilon_trace()) } else { None }, ongoing: None }
+    }
+}
+
+// the anchor builds no switch nor node, it is an empty node
+impl TreeBuilder for EoiReader {
+    fn tag(&self) -> Tag {
+        None
+    }
+
+    fn switch_builder(&self, _: usize) -> SwitchBuilder {
+        SwitchBuilder::Case(self, None)
+    }
+
+    fn node_builder(&self) -> NodeBuilder {
+        (Box::new(empty()), None)
+    }
+}
@@ -1,4 +1,5 @@
 use reader::*;
+use regex::Regex;
 use std::fmt::Debug;
 use std::fmt::Error;
 use std::fmt::Formatter;
@@ -17,6 +18,10 @@ impl<Tk: Token> Reader<Tk> for EpsilonReader {
     fn read(&self, _: &Rc<dyn Reader<Tk>>, _: Tk) -> ReadingResult<Tk> {
         unimplemented!()
     }
+
+    fn regex(&self) -> Option<Regex> {
+        Some(Regex::Empty)
+    }
 }
 
 impl Debug for EpsilonReader {
@@ -0,0 +1,149 @@
+use list::Stack;
+use reader::*;
+use std::fmt::Debug;
+use std::fmt::Error;
+use std::fmt::Formatter;
+use std::rc::Rc;
+use symbols::Tag;
+use traces::Trace;
+use trees::*;
+
+// an ongoing reader whose successes get the frames of the readers it was decided in, innermost first
+pub struct FramedReader<Tk: Token> {
+    reader: Rc<dyn Reader<Tk>>,
+    frames: Rc<Vec<Trace>>,
+}
+
+impl<Tk: Token> Debug for FramedReader<Tk> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "{:?}", self.reader)
+    }
+}
+
+// nested frames are merged, so that a chain of decided readers reads through a single layer
+fn framed_with<Tk: Token + 'static>(reader: Rc<dyn Reader<Tk>>, frames: Rc<Vec<Trace>>) -> Rc<dyn Reader<Tk>> {
+    match reader.frames() {
+        Some((inner, inner_frames)) => {
+            let frames = inner_frames.iter().chain(frames.iter()).cloned().collect();
+            rc_reader(FramedReader { reader: inner, frames: Rc::new(frames) })
+        }
+        None => rc_reader(FramedReader { reader, frames }),
+    }
+}
+
+pub fn rc_framed<Tk: Token + 'static>(reader: Rc<dyn Reader<Tk>>, frames: Vec<Trace>) -> Rc<dyn Reader<Tk>> {
+    framed_with(reader, Rc::new(frames))
+}
+
+impl<Tk: Token + 'static> FramedReader<Tk> {
+    fn process(&self, res: ReadingResult<Tk>) -> ReadingResult<Tk> {
+        ReadingResult {
+            success: res.success.map(|success| self.frames.iter().fold(success, |success, frame| success.push(frame.clone()))),
+            ongoing: res.ongoing.map(|ongoing| framed_with(ongoing, self.frames.clone())),
+        }
+    }
+}
+
+impl<Tk: Token + 'static> Reader<Tk> for FramedReader<Tk> {
+    fn epsilon(&self, _: &Rc<dyn Reader<Tk>>) -> ReadingResult<Tk> {
+        self.process(epsilon(&self.reader))
+    }
+
+    fn read(&self, _: &Rc<dyn Reader<Tk>>, token: Tk) -> ReadingResult<Tk> {
+        self.process(read(&self.reader, token))
+    }
+
+    fn ongoing_children(&self) -> Vec<Rc<dyn Reader<Tk>>> {
+        vec![self.reader.clone()]
+    }
+
+    fn frames(&self) -> Option<(Rc<dyn Reader<Tk>>, &[Trace])> {
+        Some((self.reader.clone(), &self.frames))
+    }
+}
+
+impl<Tk: Token> TreeBuilder for FramedReader<Tk> {
+    fn tag(&self) -> Tag {
+        None
+    }
+
+    fn switch_builder(&self, _: usize) -> SwitchBuilder {
+        unimplemented!()
+    }
+
+    fn node_builder(&self) -> NodeBuilder {
+        unimplemented!()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reader::list_reader::ListReader;
+    use reader::switch_reader::SwitchReader;
+    use reader::token_reader::TokenReader;
+    use super::*;
+    use symbols::SymbolTable;
+    use traces::Policy;
+    use trees::tree_from_trace_at;
+
+    const A: TokenId = 0;
+    const B: TokenId = 1;
+    const C: TokenId = 2;
+
+    fn token(id: TokenId) -> Rc<dyn Reader<TokenId>> {
+        rc_reader(TokenReader { token_ref: id, tag: None })
+    }
+
+    fn list(elts: Vec<Rc<dyn Reader<TokenId>>>, tag: Tag) -> Rc<dyn Reader<TokenId>> {
+        rc_reader(ListReader::new(elts, tag))
+    }
+
+    fn switch(cases: Vec<Rc<dyn Reader<TokenId>>>) -> Rc<dyn Reader<TokenId>> {
+        rc_reader(SwitchReader::new(cases, Policy::Longest, None))
+    }
+
+    fn ongoing(reader: &Rc<dyn Reader<TokenId>>, tokens: &[TokenId]) -> Rc<dyn Reader<TokenId>> {
+        tokens.iter().fold(reader.clone(), |reader, token| read(&reader, *token).ongoing.unwrap())
+    }
+
+    // the cases of the switches the ongoing reader was decided in, innermost first
+    fn decided(reader: &Rc<dyn Reader<TokenId>>) -> Vec<usize> {
+        reader.frames().map_or(Vec::new(), |(_, frames)| frames.iter().map(|frame| match *frame {
+            Trace::Switch(case, _) => case,
+            _ => unreachable!(),
+        }).collect())
+    }
+
+    #[test]
+    fn decided_switches_are_framed() {
+        let mut table = SymbolTable::new();
+        let grammar = switch(vec![
+            list(vec![token(A), token(B)], Some(table.get("ab"))),
+            list(vec![token(C), token(B)], Some(table.get("cb"))),
+        ]);
+        let after_c = ongoing(&grammar, &[C]);
+        assert_eq!(decided(&after_c), vec![1]);
+        let success = read(&after_c, B).success.unwrap();
+        let tree = tree_from_trace_at(grammar.as_tree_builder(), &success, &[C, B], 0);
+        assert_eq!(tree.tag(), Some(table.get("cb")));
+        assert_eq!(tree.leaves().count(), 2);
+    }
+
+    #[test]
+    fn nested_frames_are_merged() {
+        let grammar = switch(vec![
+            token(C),
+            switch(vec![token(C), list(vec![token(A), token(B)], None)]),
+        ]);
+        let after_a = ongoing(&grammar, &[A]);
+        assert_eq!(decided(&after_a), vec![1, 1]);
+        assert!(after_a.frames().unwrap().0.frames().is_none());
+    }
+
+    #[test]
+    fn shadowed_cases_are_dropped() {
+        let ab = list(vec![token(A), token(B)], None);
+        let after_a = ongoing(&switch(vec![ab.clone(), ab]), &[A]);
+        assert_eq!(decided(&after_a), vec![0]);
+    }
+}
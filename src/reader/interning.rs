@@ -0,0 +1,158 @@
+use list::List;
+use reader::*;
+use std::any::Any;
+use std::any::TypeId;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::BuildHasherDefault;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::rc::Rc;
+use std::rc::Weak;
+use symbols::Tag;
+use traces::StackedReader;
+
+// the type of a reader with its fields, children and traces being compared by address: once
+// children are shared, two readers with the same key read alike and build the same traces
+#[derive(PartialEq, Eq)]
+struct ReaderKey {
+    // cached, the table being rehashed as it grows
+    hash: u64,
+    ty: TypeId,
+    shape: Vec<usize>,
+}
+
+impl ReaderKey {
+    fn new(ty: TypeId, shape: Vec<usize>) -> Self {
+        let mut hasher = KeyHasher::default();
+        ty.hash(&mut hasher);
+        shape.hash(&mut hasher);
+        ReaderKey { hash: hasher.finish(), ty, shape }
+    }
+}
+
+impl Hash for ReaderKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash);
+    }
+}
+
+pub fn address<T: ?Sized>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as *const u8 as usize
+}
+
+pub fn option_address<T: ?Sized>(rc: &Option<Rc<T>>) -> usize {
+    rc.as_ref().map_or(0, address)
+}
+
+pub fn tag_key(tag: Tag) -> usize {
+    tag.map_or(0, |sym| sym.get())
+}
+
+// past their first element, lists and loops carry the traces read so far, which are seldom read
+// again: only the fresh ones are shared
+pub fn is_fresh(stacked: &StackedReader) -> bool {
+    match *stacked {
+        List::Cons(..) => false,
+        List::Nil(_) => true,
+    }
+}
+
+// keys are short vectors of addresses, which need no protection against collision attacks
+#[derive(Default)]
+struct KeyHasher(u64);
+
+impl Hasher for KeyHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write_u64(*byte as u64);
+        }
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = (self.0.rotate_left(5) ^ n).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.write_u64(n as u64);
+    }
+}
+
+// the live readers by key, the dead ones being purged each time the table doubles
+struct Interner<Tk: Token> {
+    readers: HashMap<ReaderKey, Weak<dyn Reader<Tk>>, BuildHasherDefault<KeyHasher>>,
+    purge_at: usize,
+}
+
+const INTERNER_MIN_PURGE: usize = 1024;
+
+impl<Tk: Token + 'static> Interner<Tk> {
+    fn new() -> Self {
+        Interner { readers: HashMap::default(), purge_at: INTERNER_MIN_PURGE }
+    }
+
+    fn intern<R: Reader<Tk> + 'static>(&mut self, key: ReaderKey, reader: R) -> Rc<dyn Reader<Tk>> {
+        if let Some(shared) = self.readers.get(&key).and_then(|weak| weak.upgrade()) {
+            return shared;
+        }
+        if self.readers.len() >= self.purge_at {
+            self.readers.retain(|_, weak| weak.strong_count() > 0);
+            self.purge_at = (2 * self.readers.len()).max(INTERNER_MIN_PURGE);
+        }
+        let reader = rc_reader(reader);
+        self.readers.insert(key, Rc::downgrade(&reader));
+        reader
+    }
+}
+
+thread_local! {
+    // one interner per token type, there are few of them
+    static INTERNERS: RefCell<Vec<(TypeId, Box<dyn Any>)>> = RefCell::new(Vec::new());
+}
+
+// shares the reader with an equal live one, see `Reader::shape`
+pub fn rc_interned<Tk: Token + 'static, R: Reader<Tk> + 'static>(reader: R) -> Rc<dyn Reader<Tk>> {
+    let key = match reader.shape() {
+        Some(shape) => ReaderKey::new(TypeId::of::<R>(), shape),
+        None => return rc_reader(reader),
+    };
+    INTERNERS.with(|interners| {
+        let mut interners = interners.borrow_mut();
+        let tk = TypeId::of::<Tk>();
+        let i = match interners.iter().position(|interner| interner.0 == tk) {
+            Some(i) => i,
+            None => {
+                interners.push((tk, Box::new(Interner::<Tk>::new())));
+                interners.len() - 1
+            }
+        };
+        interners[i].1.downcast_mut::<Interner<Tk>>().unwrap().intern(key, reader)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use reader::loop_reader::LoopOrdering;
+    use reader::loop_reader::LoopReader;
+    use super::*;
+    use test_grammars::*;
+    use traces::Policy;
+
+    #[test]
+    fn fresh_readers_are_shared() {
+        let star = rc_reader(LoopReader::new(token(A), Policy::Longest, LoopOrdering::Increasing, None));
+        assert!(Rc::ptr_eq(&epsilon(&star).ongoing.unwrap(), &epsilon(&star).ongoing.unwrap()));
+    }
+
+    #[test]
+    fn stacked_readers_are_not_shared() {
+        let grammar = grammar();
+        let (open, again) = (read(&grammar, OPEN).ongoing.unwrap(), read(&grammar, OPEN).ongoing.unwrap());
+        assert!(!Rc::ptr_eq(&open, &again));
+        assert_eq!(open.shape(), None);
+    }
+}
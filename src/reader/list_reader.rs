@@ -1,5 +1,8 @@
 use list::*;
 use reader::*;
+use reader::interning::*;
+use regex::group;
+use regex::Regex;
 use reader::policy_reader::*;
 use std::fmt::Debug;
 use std::fmt::Error;
@@ -9,6 +12,8 @@ use symbols::Tag;
 use traces::*;
 use trees::*;
 
+// the stacked reader comes first, see `AsStackedReader`
+#[repr(C)]
 pub struct ListReader<Tk: Token> {
     stacked: StackedReader,
     pub elts: Rc<Vec<Rc<dyn Reader<Tk>>>>,
@@ -55,7 +60,7 @@ impl<Tk: Token + 'static> ListReader<Tk> {
     }
 
     fn replace(&self, _: &Rc<dyn Reader<Tk>>, ongoing: Rc<dyn Reader<Tk>>) -> Rc<dyn Reader<Tk>> {
-        rc_reader(ListReader {
+        rc_interned(ListReader {
             stacked: self.stacked.clone(),
             elts: self.elts.clone(),
             cur_elt: Some(ongoing),
@@ -83,6 +88,18 @@ impl<Tk: Token + 'static> ListReader<Tk> {
     }
 }
 
+// the traces of the elements of a list, from its success trace
+pub fn element_traces(traces: &Rc<List<Trace, TraceEnding>>) -> Vec<Rc<List<Trace, TraceEnding>>> {
+    match *traces.peek() {
+        Trace::Tmp(ref stacked) => {
+            let mut traces: Vec<_> = stacked.iter().cloned().collect();
+            traces.reverse();
+            traces
+        }
+        _ => unimplemented!(),
+    }
+}
+
 impl<Tk: Token + 'static> Reader<Tk> for ListReader<Tk> {
     fn epsilon(&self, this: &Rc<dyn Reader<Tk>>) -> ReadingResult<Tk> {
         self.process(this, epsilon)
@@ -91,6 +108,26 @@ impl<Tk: Token + 'static> Reader<Tk> for ListReader<Tk> {
     fn read(&self, this: &Rc<dyn Reader<Tk>>, token: Tk) -> ReadingResult<Tk> {
         self.process(this, |r| read(r, token))
     }
+
+    fn ongoing_children(&self) -> Vec<Rc<dyn Reader<Tk>>> {
+        self.cur_elt.iter().cloned().collect()
+    }
+
+    fn shape(&self) -> Option<Vec<usize>> {
+        // only the states of the grammar itself are shared, the others are seldom met again
+        if !is_fresh(&self.stacked) || !self.cur_elt.as_ref().is_some_and(|elt| Rc::ptr_eq(elt, &self.elts[self.cursor])) { return None; }
+        Some(vec![address(&self.elts), option_address(&self.cur_elt), self.cursor, tag_key(self.tag)])
+    }
+
+    fn grammar_children(&self) -> Vec<Rc<dyn Reader<Tk>>> {
+        self.elts.to_vec()
+    }
+
+    fn regex(&self) -> Option<Regex> {
+        if self.cursor > 0 { return None; }
+        let elts = self.elts.iter().map(|elt| elt.regex()).collect::<Option<Vec<Regex>>>()?;
+        Some(group(Regex::Concat(elts), self.tag))
+    }
 }
 
 impl<Tk: Token + 'static> TreeBuilder for ListReader<Tk> {
@@ -1,5 +1,8 @@
 use list::*;
 use reader::*;
+use reader::interning::*;
+use regex::group;
+use regex::Regex;
 use reader::policy_reader::*;
 use std::fmt::Debug;
 use std::fmt::Error;
@@ -16,6 +19,8 @@ pub enum LoopOrdering {
     Decreasing = -1,
 }
 
+// the stacked reader comes first, see `AsStackedReader`
+#[repr(C)]
 pub struct LoopReader<Tk: Token> {
     stacked: StackedReader,
     pub ref_: Rc<dyn Reader<Tk>>,
@@ -64,7 +69,7 @@ impl<Tk: Token + 'static> LoopReader<Tk> {
     }
 
     fn replace(&self, _: &Rc<dyn Reader<Tk>>, ongoing: Rc<dyn Reader<Tk>>) -> Rc<dyn Reader<Tk>> {
-        rc_reader(LoopReader {
+        rc_interned(LoopReader {
             stacked: self.stacked.clone(),
             ref_: self.ref_.clone(),
             variant: ongoing,
@@ -88,10 +93,29 @@ impl<Tk: Token + 'static> Reader<Tk> for LoopReader<Tk> {
         let ongoing = ongoing.map(|o| self.replace(this, o));
         let ongoing = LoopPolicyReader::of(success.clone(), ongoing, success_trace, self.cursor + 1);
         ReadingResult {
-            success: success.map(|success| stacked_trace().push(Trace::Tmp(Self::as_stacked_reader(&(success as Rc<dyn Reader<Tk>>)))).push(Trace::Switch(self.cursor * (self.ordering as usize), self.policy))),
+            success: success.map(|success| stacked_trace().push(Trace::Tmp(Self::as_stacked_reader(&(success as Rc<dyn Reader<Tk>>)))).push(Trace::Switch(self.cursor.wrapping_mul(self.ordering as usize), self.policy))),
             ongoing,
         }
     }
+
+    fn ongoing_children(&self) -> Vec<Rc<dyn Reader<Tk>>> {
+        vec![self.variant.clone()]
+    }
+
+    fn shape(&self) -> Option<Vec<usize>> {
+        // only the states of the grammar itself are shared, the others are seldom met again
+        if !is_fresh(&self.stacked) || !Rc::ptr_eq(&self.variant, &self.ref_) { return None; }
+        Some(vec![address(&self.ref_), address(&self.variant), self.cursor, self.policy as usize, self.ordering as isize as usize, tag_key(self.tag)])
+    }
+
+    fn grammar_children(&self) -> Vec<Rc<dyn Reader<Tk>>> {
+        vec![self.ref_.clone()]
+    }
+
+    fn regex(&self) -> Option<Regex> {
+        if self.cursor > 0 { return None; }
+        Some(group(Regex::Repeat(Box::new(self.ref_.regex()?), 0, None), self.tag))
+    }
 }
 
 impl<Tk: Token> TreeBuilder for LoopReader<Tk> {
@@ -1,39 +1,192 @@
 use reader::*;
+use reader::classes::Classes;
+use reader::classes::classes_of;
+use regex::Regex;
+use std::cell::OnceCell;
 use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::fmt::Debug;
 use std::fmt::Error;
 use std::fmt::Formatter;
 use std::rc::Rc;
 use symbols::Tag;
+use traces::Trace;
 use trees::*;
 
-pub struct Memoized<Tk: Token, R: Reader<Tk>> {
-    reader: R,
-    eps: Option<ReadingResult<Tk>>,
-    reads: Vec<Option<ReadingResult<Tk>>>,
+// the reading results of a memoized reader, by token class
+pub trait MemoTable<Tk: Token>: 'static {
+    fn get(&mut self, class: usize) -> Option<ReadingResult<Tk>>;
+    fn insert(&mut self, class: usize, res: ReadingResult<Tk>);
+    // an empty table of the same kind, for the readers derived from the memoized one
+    fn emptied(&self) -> Self;
 }
 
-impl<Tk: Token, R: Reader<Tk>> Debug for Memoized<Tk, R> {
-    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        self.reader.fmt(f)
+// indexed by class, growing up to the classes read
+pub struct Dense<Tk: Token>(Vec<Option<ReadingResult<Tk>>>);
+
+impl<Tk: Token> Dense<Tk> {
+    pub fn new() -> Self {
+        Dense(Vec::new())
+    }
+}
+
+impl<Tk: Token + 'static> MemoTable<Tk> for Dense<Tk> {
+    fn get(&mut self, class: usize) -> Option<ReadingResult<Tk>> {
+        self.0.get(class).and_then(|res| res.clone())
+    }
+
+    fn insert(&mut self, class: usize, res: ReadingResult<Tk>) {
+        if class >= self.0.len() { self.0.resize(class + 1, None); }
+        self.0[class] = Some(res);
+    }
+
+    fn emptied(&self) -> Self {
+        Dense::new()
+    }
+}
+
+// the classes read, searched in order: most states only read a few classes
+pub struct Sparse<Tk: Token>(Vec<(usize, ReadingResult<Tk>)>);
+
+impl<Tk: Token> Sparse<Tk> {
+    pub fn new() -> Self {
+        Sparse(Vec::new())
     }
 }
 
-impl<Tk: Token, R: Reader<Tk> + 'static> Memoized<Tk, R> {
-    fn new(reader: R, n: usize) -> Self {
-        Memoized {
-            reader,
-            eps: None,
-            reads: vec![None; n],
+impl<Tk: Token + 'static> MemoTable<Tk> for Sparse<Tk> {
+    fn get(&mut self, class: usize) -> Option<ReadingResult<Tk>> {
+        self.0.iter().find(|entry| entry.0 == class).map(|entry| entry.1.clone())
+    }
+
+    fn insert(&mut self, class: usize, res: ReadingResult<Tk>) {
+        self.0.push((class, res));
+    }
+
+    fn emptied(&self) -> Self {
+        Sparse::new()
+    }
+}
+
+// sparse while it holds few classes, dense past them
+pub enum Adaptive<Tk: Token> {
+    Sparse(Sparse<Tk>),
+    Dense(Dense<Tk>),
+}
+
+const ADAPTIVE_MAX_SPARSE: usize = 8;
+
+impl<Tk: Token> Adaptive<Tk> {
+    pub fn new() -> Self {
+        Adaptive::Sparse(Sparse::new())
+    }
+}
+
+impl<Tk: Token + 'static> MemoTable<Tk> for Adaptive<Tk> {
+    fn get(&mut self, class: usize) -> Option<ReadingResult<Tk>> {
+        match *self {
+            Adaptive::Sparse(ref mut table) => table.get(class),
+            Adaptive::Dense(ref mut table) => table.get(class),
         }
     }
 
-    fn as_memoized(reader: &R) -> &Self {
-        unsafe { &*(reader as *const R as *const Self) }
+    fn insert(&mut self, class: usize, res: ReadingResult<Tk>) {
+        let dense = match *self {
+            Adaptive::Sparse(ref mut table) if table.0.len() == ADAPTIVE_MAX_SPARSE => {
+                let mut dense = Dense::new();
+                for (class, res) in table.0.drain(..) { dense.insert(class, res); }
+                dense
+            }
+            Adaptive::Sparse(ref mut table) => return table.insert(class, res),
+            Adaptive::Dense(ref mut table) => return table.insert(class, res),
+        };
+        *self = Adaptive::Dense(dense);
+        self.insert(class, res)
+    }
+
+    fn emptied(&self) -> Self {
+        Adaptive::new()
     }
 }
 
-impl<Tk: Token, R: Reader<Tk>> TreeBuilder for Memoized<Tk, R> {
+// the last classes read, most recent first
+pub struct Lru<Tk: Token> {
+    entries: VecDeque<(usize, ReadingResult<Tk>)>,
+    capacity: usize,
+}
+
+impl<Tk: Token> Lru<Tk> {
+    pub fn new(capacity: usize) -> Self {
+        Lru { entries: VecDeque::new(), capacity }
+    }
+}
+
+impl<Tk: Token + 'static> MemoTable<Tk> for Lru<Tk> {
+    fn get(&mut self, class: usize) -> Option<ReadingResult<Tk>> {
+        let i = self.entries.iter().position(|entry| entry.0 == class)?;
+        let entry = self.entries.remove(i).unwrap();
+        let res = entry.1.clone();
+        self.entries.push_front(entry);
+        Some(res)
+    }
+
+    fn insert(&mut self, class: usize, res: ReadingResult<Tk>) {
+        if self.entries.len() == self.capacity { self.entries.pop_back(); }
+        if self.capacity > 0 { self.entries.push_front((class, res)); }
+    }
+
+    fn emptied(&self) -> Self {
+        Lru::new(self.capacity)
+    }
+}
+
+// the classes are computed on the first read, once the references of the grammar are set
+struct Alphabet<Tk: Token> {
+    grammar: Rc<dyn Reader<Tk>>,
+    classes: OnceCell<Classes>,
+}
+
+impl<Tk: Token> Alphabet<Tk> {
+    fn class(&self, id: TokenId) -> usize {
+        self.classes.get_or_init(|| classes_of(::std::slice::from_ref(&self.grammar))).class(id)
+    }
+}
+
+pub struct Memoized<Tk: Token, M: MemoTable<Tk>> {
+    reader: Rc<dyn Reader<Tk>>,
+    alphabet: Rc<Alphabet<Tk>>,
+    eps: RefCell<Option<ReadingResult<Tk>>>,
+    reads: RefCell<M>,
+    // whether the readers derived from this one are memoized too, alike, see `rc_memo_states`
+    states: bool,
+}
+
+impl<Tk: Token, M: MemoTable<Tk>> Debug for Memoized<Tk, M> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        self.reader.fmt(f)
+    }
+}
+
+impl<Tk: Token + 'static, M: MemoTable<Tk>> Memoized<Tk, M> {
+    fn derived(&self, res: ReadingResult<Tk>) -> ReadingResult<Tk> {
+        ReadingResult {
+            success: res.success,
+            ongoing: res.ongoing.map(|ongoing| if self.states {
+                rc_reader(Memoized {
+                    reader: ongoing,
+                    alphabet: self.alphabet.clone(),
+                    eps: RefCell::new(None),
+                    reads: RefCell::new(self.reads.borrow().emptied()),
+                    states: true,
+                })
+            } else {
+                ongoing
+            }),
+        }
+    }
+}
+
+impl<Tk: Token, M: MemoTable<Tk>> TreeBuilder for Memoized<Tk, M> {
     fn tag(&self) -> Tag {
         self.reader.tag()
     }
@@ -51,56 +204,143 @@ impl<Tk: Token, R: Reader<Tk>> TreeBuilder for Memoized<Tk, R> {
     }
 }
 
-impl<Tk: Token + 'static, R: Reader<Tk> + 'static> Reader<Tk> for Memoized<Tk, R> {
-    fn epsilon(&self, this: &Rc<dyn Reader<Tk>>) -> ReadingResult<Tk> {
-        let eps = unsafe { &mut *(&self.eps as *const _ as *mut Option<ReadingResult<Tk>>) };
-        match eps {
-            Some(ref memo) => memo.clone(),
-            None => {
-                let tmp = self.reader.epsilon(this);
-                *eps = Some(tmp.clone());
-                tmp
-            }
+impl<Tk: Token + 'static, M: MemoTable<Tk>> Reader<Tk> for Memoized<Tk, M> {
+    fn epsilon(&self, _: &Rc<dyn Reader<Tk>>) -> ReadingResult<Tk> {
+        if let Some(ref memo) = *self.eps.borrow() {
+            return memo.clone();
         }
+        let res = self.derived(epsilon(&self.reader));
+        *self.eps.borrow_mut() = Some(res.clone());
+        res
     }
 
-    fn read(&self, this: &Rc<dyn Reader<Tk>>, token: Tk) -> ReadingResult<Tk> {
-        let id = token.id();
-        let mut res = unsafe { &mut *(&self.reads[id as usize] as *const _ as *mut Option<ReadingResult<Tk>>) };
-        match res {
-            Some(ref memo) => memo.clone(),
-            None => {
-                let tmp = self.reader.read(this, token);
-                *res = Some(tmp.clone());
-                tmp
-            }
+    fn read(&self, _: &Rc<dyn Reader<Tk>>, token: Tk) -> ReadingResult<Tk> {
+        let class = self.alphabet.class(token.id());
+        if let Some(memo) = self.reads.borrow_mut().get(class) {
+            return memo;
         }
+        let res = self.derived(read(&self.reader, token));
+        self.reads.borrow_mut().insert(class, res.clone());
+        res
+    }
+
+    fn ongoing_children(&self) -> Vec<Rc<dyn Reader<Tk>>> {
+        self.reader.ongoing_children()
+    }
+
+    fn frames(&self) -> Option<(Rc<dyn Reader<Tk>>, &[Trace])> {
+        self.reader.frames()
     }
+
+    fn grammar_children(&self) -> Vec<Rc<dyn Reader<Tk>>> {
+        vec![self.reader.clone()]
+    }
+
+    fn regex(&self) -> Option<Regex> {
+        self.reader.regex()
+    }
+}
+
+fn rc_memoized_with<Tk: Token + 'static, M: MemoTable<Tk>>(reader: Rc<dyn Reader<Tk>>, table: M, states: bool) -> Rc<dyn Reader<Tk>> {
+    let alphabet = Rc::new(Alphabet { grammar: reader.clone(), classes: OnceCell::new() });
+    rc_reader(Memoized { reader, alphabet, eps: RefCell::new(None), reads: RefCell::new(table), states })
 }
 
-pub fn rc_memo_reader<Tk: Token + 'static, R: Reader<Tk> + 'static>(reader: R, n: usize) -> Rc<dyn Reader<Tk>> {
-    Rc::new(Memoized::new(reader, n))
+// memoizes the reads by token class, the classes being inferred from the reader, see `Adaptive`
+pub fn rc_memo_reader<Tk: Token + 'static, R: Reader<Tk> + 'static>(reader: R) -> Rc<dyn Reader<Tk>> {
+    rc_memoized(rc_reader(reader), Adaptive::new())
 }
 
-pub(super) fn rc_memo_reader_from<Tk: Token + 'static, R: Reader<Tk> + 'static>(reader: R, from: &R) -> Rc<dyn Reader<Tk>> {
-    Rc::new(Memoized::new(reader, Memoized::as_memoized(from).reads.len()))
+// only the reads of the reader itself are memoized, not those of the readers derived from it
+pub fn rc_memoized<Tk: Token + 'static, M: MemoTable<Tk>>(reader: Rc<dyn Reader<Tk>>, table: M) -> Rc<dyn Reader<Tk>> {
+    rc_memoized_with(reader, table, false)
 }
 
-pub trait MemoAllocator {
-    fn rc<Tk: 'static + Token, R: 'static + Reader<Tk>>(reader: R, from: &R) -> Rc<dyn Reader<Tk>>;
+// the reads of the readers derived from the reader are memoized too, each in a table like
+// `table`: reading the same tokens again gives the same readers, but every state met is kept
+pub fn rc_memo_states<Tk: Token + 'static, M: MemoTable<Tk>>(reader: Rc<dyn Reader<Tk>>, table: M) -> Rc<dyn Reader<Tk>> {
+    rc_memoized_with(reader, table, true)
 }
 
-pub struct WithMemo;
-pub struct WithoutMemo;
+#[cfg(test)]
+mod tests {
+    use list::Stack;
+    use reader::list_reader::ListReader;
+    use reader::token_reader::TokenReader;
+    use super::*;
+    use traces::Policy;
+    use traces::token_trace;
 
-impl MemoAllocator for WithMemo {
-    fn rc<Tk: 'static + Token, R: 'static + Reader<Tk>>(reader: R, from: &R) -> Rc<dyn Reader<Tk>> {
-        rc_memo_reader_from(reader, from)
+    // a result telling its number
+    fn result(n: usize) -> ReadingResult<TokenId> {
+        ReadingResult { success: Some(token_trace().push(Trace::Switch(n, Policy::Longest))), ongoing: None }
+    }
+
+    fn number(res: Option<ReadingResult<TokenId>>) -> Option<usize> {
+        res.map(|res| match *res.success.unwrap().peek() {
+            Trace::Switch(n, _) => n,
+            _ => unreachable!(),
+        })
+    }
+
+    fn keeps_every_class<M: MemoTable<TokenId>>(mut table: M) -> M {
+        for class in &[3, 0, 12] { table.insert(*class, result(10 * class)); }
+        for class in &[0, 3, 12] { assert_eq!(number(table.get(*class)), Some(10 * class)); }
+        assert_eq!(number(table.get(1)), None);
+        assert_eq!(number(table.emptied().get(3)), None);
+        table
+    }
+
+    #[test]
+    fn dense_and_sparse_tables_keep_every_class() {
+        keeps_every_class(Dense::new());
+        keeps_every_class(Sparse::new());
+    }
+
+    #[test]
+    fn adaptive_tables_turn_dense_past_a_few_classes() {
+        let mut table = Adaptive::new();
+        for class in 0..ADAPTIVE_MAX_SPARSE { table.insert(class, result(class)); }
+        assert!(match table { Adaptive::Sparse(_) => true, Adaptive::Dense(_) => false });
+        table.insert(ADAPTIVE_MAX_SPARSE, result(ADAPTIVE_MAX_SPARSE));
+        assert!(match table { Adaptive::Sparse(_) => false, Adaptive::Dense(_) => true });
+        for class in 0..ADAPTIVE_MAX_SPARSE + 1 { assert_eq!(number(table.get(class)), Some(class)); }
+        assert!(match keeps_every_class(table.emptied()) { Adaptive::Sparse(_) => true, Adaptive::Dense(_) => false });
+    }
+
+    #[test]
+    fn lru_tables_forget_the_least_recently_read_class() {
+        let mut table = Lru::new(2);
+        table.insert(1, result(1));
+        table.insert(2, result(2));
+        assert_eq!(number(table.get(1)), Some(1));
+        table.insert(3, result(3));
+        assert_eq!(number(table.get(2)), None);
+        assert_eq!(number(table.get(1)), Some(1));
+        assert_eq!(number(table.get(3)), Some(3));
+        let mut table = Lru::new(0);
+        table.insert(1, result(1));
+        assert_eq!(number(table.get(1)), None);
+    }
+
+    fn list() -> Rc<dyn Reader<TokenId>> {
+        let token = |id: TokenId| rc_reader(TokenReader { token_ref: id, tag: None }) as Rc<dyn Reader<TokenId>>;
+        rc_reader(ListReader::new(vec![token(0), token(1), token(2)], None))
+    }
+
+    #[test]
+    fn memoized_readers_keep_their_reads() {
+        let memoized = rc_memoized(list(), Lru::new(1));
+        let after_0 = read(&memoized, 0).ongoing.unwrap();
+        assert!(Rc::ptr_eq(&after_0, &read(&memoized, 0).ongoing.unwrap()));
+        // the list itself, not a memoized reader wrapping it
+        assert_eq!(after_0.grammar_children().len(), 3);
     }
-}
 
-impl MemoAllocator for WithoutMemo {
-    fn rc<Tk: 'static + Token, R: 'static + Reader<Tk>>(reader: R, _: &R) -> Rc<dyn Reader<Tk>> {
-        rc_reader(reader)
+    #[test]
+    fn memoized_states_keep_their_reads() {
+        let memoized = rc_memo_states(list(), Lru::new(1));
+        let after_01 = |memoized: &Rc<dyn Reader<TokenId>>| read(&read(memoized, 0).ongoing.unwrap(), 1).ongoing.unwrap();
+        assert!(Rc::ptr_eq(&after_01(&memoized), &after_01(&memoized)));
     }
 }
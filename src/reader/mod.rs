@@ -1,4 +1,5 @@
 use list::List;
+use regex::Regex;
 use std::fmt::Debug;
 use std::rc::Rc;
 use traces::Trace;
@@ -6,6 +7,7 @@ use traces::TraceEnding;
 use trees::*;
 
 pub mod epsilon_reader;
+pub mod eoi_reader;
 pub mod list_reader;
 pub mod loop_reader;
 pub mod policy_reader;
@@ -16,6 +18,9 @@ pub mod token_reader;
 pub mod conditional_token_reader;
 pub mod optional_reader;
 pub mod memoization;
+pub mod interning;
+pub mod framed_reader;
+pub mod classes;
 
 pub type TokenId = usize;
 
@@ -59,6 +64,31 @@ impl<Tk: Token> ReadingResult<Tk> {
 pub trait Reader<Tk: Token>: TreeBuilder + Debug {
     fn epsilon(&self, this: &Rc<dyn Reader<Tk>>) -> ReadingResult<Tk>;
     fn read(&self, this: &Rc<dyn Reader<Tk>>, token: Tk) -> ReadingResult<Tk>;
+    // the ongoing readers this ongoing reader reads through
+    fn ongoing_children(&self) -> Vec<Rc<dyn Reader<Tk>>> {
+        Vec::new()
+    }
+    // the fields of the reader, children by address, see `interning::rc_interned`; None when it
+    // cannot be shared
+    fn shape(&self) -> Option<Vec<usize>> {
+        None
+    }
+    // the reader and the frames pushed on its successes, for a `framed_reader::FramedReader`
+    fn frames(&self) -> Option<(Rc<dyn Reader<Tk>>, &[Trace])> {
+        None
+    }
+    // the readers this reader is built from
+    fn grammar_children(&self) -> Vec<Rc<dyn Reader<Tk>>> {
+        Vec::new()
+    }
+    // the sets of token ids this reader tells apart, its children aside, see `classes::classes_of`
+    fn token_sets(&self) -> Vec<Vec<TokenId>> {
+        Vec::new()
+    }
+    // the language of the reader, before any read, over the token ids below 256; None when not regular
+    fn regex(&self) -> Option<Regex> {
+        None
+    }
 }
 
 pub fn epsilon<Tk: Token>(this: &Rc<dyn Reader<Tk>>) -> ReadingResult<Tk> {
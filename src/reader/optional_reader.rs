@@ -1,5 +1,6 @@
 use list::Stack;
 use reader::*;
+use regex::Regex;
 use reader::epsilon_reader::EpsilonReader;
 use std::fmt::Debug;
 use std::fmt::Error;
@@ -39,6 +40,14 @@ impl<Tk: Token + 'static> Reader<Tk> for OptionalReader<Tk> {
     fn read(&self, _: &Rc<dyn Reader<Tk>>, token: Tk) -> ReadingResult<Tk> {
         unimplemented!()
     }
+
+    fn grammar_children(&self) -> Vec<Rc<dyn Reader<Tk>>> {
+        vec![self.reader.clone()]
+    }
+
+    fn regex(&self) -> Option<Regex> {
+        Some(Regex::Repeat(Box::new(self.reader.regex()?), 0, Some(1)))
+    }
 }
 
 impl<Tk: Token> TreeBuilder for OptionalReader<Tk> {
@@ -14,11 +14,21 @@ pub struct PolicyReader<Tk: Token> {
     trace_index: usize,
 }
 
-pub trait Decide<Tk: Token> where Self: 'static + Sized + Reader<Tk> {
+impl<Tk: Token> PolicyReader<Tk> {
+    fn children(&self) -> Vec<Rc<dyn Reader<Tk>>> {
+        vec![self.succeeded.clone(), self.still_ongoing.clone()]
+    }
+}
+
+pub trait Decide<Tk: Token + 'static> where Self: 'static + Sized + Reader<Tk> {
     fn new(policy_reader: PolicyReader<Tk>) -> Self;
 
     fn of(succeeded: Option<Rc<dyn Reader<Tk>>>, still_ongoing: Option<Rc<dyn Reader<Tk>>>,
           success_trace: Option<Rc<List<Trace, TraceEnding>>>, trace_index: usize) -> Option<Rc<dyn Reader<Tk>>> {
+        // branches sharing their reader read alike
+        if let (&Some(ref succeeded), &Some(ref still_ongoing)) = (&succeeded, &still_ongoing) {
+            if Rc::ptr_eq(succeeded, still_ongoing) { return Some(succeeded.clone()); }
+        }
         if succeeded.is_some() && still_ongoing.is_some() {
             Some(rc_reader(Self::new(PolicyReader {
                 succeeded: succeeded.unwrap(),
@@ -90,6 +100,10 @@ impl<Tk: 'static + Token> Reader<Tk> for ListPolicyReader<Tk> {
     fn read(&self, _: &Rc<dyn Reader<Tk>>, token: Tk) -> ReadingResult<Tk> {
         self.read_and_decide(token)
     }
+
+    fn ongoing_children(&self) -> Vec<Rc<dyn Reader<Tk>>> {
+        self.policy_reader().children()
+    }
 }
 
 impl<Tk: 'static + Token> Reader<Tk> for LoopPolicyReader<Tk> {
@@ -100,6 +114,10 @@ impl<Tk: 'static + Token> Reader<Tk> for LoopPolicyReader<Tk> {
     fn read(&self, _: &Rc<dyn Reader<Tk>>, token: Tk) -> ReadingResult<Tk> {
         self.read_and_decide(token)
     }
+
+    fn ongoing_children(&self) -> Vec<Rc<dyn Reader<Tk>>> {
+        self.policy_reader().children()
+    }
 }
 
 impl<Tk: Token> TreeBuilder for ListPolicyReader<Tk> {
@@ -1,4 +1,5 @@
 use reader::*;
+use std::cell::OnceCell;
 use std::fmt::Debug;
 use std::fmt::Error;
 use std::fmt::Formatter;
@@ -7,45 +8,48 @@ use symbols::Tag;
 use trees::*;
 
 pub struct RefReader<Tk: Token> {
-    pub val: Option<Rc<dyn Reader<Tk>>>
+    // set once the referenced reader is built
+    pub val: OnceCell<Rc<dyn Reader<Tk>>>
 }
 
 impl<Tk: Token> Debug for RefReader<Tk> {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        write!(f, "@{}", self.val.as_ref().and_then(|reader| reader.tag()).map(|tag| tag.to_string()).unwrap_or("".to_string()))
+        write!(f, "@{}", self.val.get().and_then(|reader| reader.tag()).map(|tag| tag.to_string()).unwrap_or("".to_string()))
     }
 }
 
 impl<Tk: Token> RefReader<Tk> {
     pub fn new() -> Self {
-        RefReader {val: None}
+        RefReader {val: OnceCell::new()}
     }
     pub fn set(this: Rc<dyn Reader<Tk>>, val: Rc<dyn Reader<Tk>>) -> Rc<dyn Reader<Tk>> {
-        unsafe {
-            let reader = &mut *(this.as_ref() as *const _  as *mut RefReader<Tk>);
-            reader.val = Some(val);
-            this
-        }
+        let reader = unsafe { &*(this.as_ref() as *const dyn Reader<Tk> as *const RefReader<Tk>) };
+        if reader.val.set(val).is_err() { panic!("reference already set"); }
+        this
     }
 }
 
 impl<Tk: Token + 'static> Reader<Tk> for RefReader<Tk> {
     fn epsilon(&self, _: &Rc<dyn Reader<Tk>>) -> ReadingResult<Tk> {
-        epsilon(self.val.as_ref().unwrap())
+        epsilon(self.val.get().unwrap())
     }
 
     fn read(&self, _: &Rc<dyn Reader<Tk>>, _: Tk) -> ReadingResult<Tk> {
         unimplemented!()
     }
+
+    fn grammar_children(&self) -> Vec<Rc<dyn Reader<Tk>>> {
+        self.val.get().into_iter().cloned().collect()
+    }
 }
 
 impl<Tk: Token> TreeBuilder for RefReader<Tk> {
     fn tag(&self) -> Tag {
-        self.val.as_ref().unwrap().tag()
+        self.val.get().unwrap().tag()
     }
 
     fn is_volatile(&self) -> VolatileBuilder {
-        Some((self.val.as_ref().unwrap().as_tree_builder(), None))
+        Some((self.val.get().unwrap().as_tree_builder(), None))
     }
 
     fn switch_builder(&self, _: usize) -> SwitchBuilder {
@@ -1,7 +1,11 @@
 use itertools::Itertools;
 use list::Stack;
 use reader::*;
-use reader::memoization::rc_memo_reader_from;
+use reader::framed_reader::rc_framed;
+use reader::interning::address;
+use reader::interning::tag_key;
+use regex::group;
+use regex::Regex;
 use std::fmt::Debug;
 use std::fmt::Error;
 use std::fmt::Formatter;
@@ -10,46 +14,48 @@ use symbols::Tag;
 use traces::Policy;
 use traces::Trace;
 use trees::*;
-use reader::memoization::MemoAllocator;
-use reader::memoization::WithoutMemo;
-use reader::memoization::WithMemo;
-use std::marker::PhantomData;
 
 type Case<Tk> = (Rc<dyn Reader<Tk>>, usize);
 
-pub struct SwitchReader_<Tk: Token, A: MemoAllocator> {
+pub struct SwitchReader<Tk: Token> {
     pub cases: Vec<Case<Tk>>,
     policy: Policy,
     pub tag: Tag,
-    phantom: PhantomData<A>
 }
 
-pub type SwitchReader<Tk> = SwitchReader_<Tk, WithoutMemo>;
-pub type MemoSwitchReader<Tk> = SwitchReader_<Tk, WithMemo>;
-
-impl<Tk: Token, A: MemoAllocator> Debug for SwitchReader_<Tk, A> {
+impl<Tk: Token> Debug for SwitchReader<Tk> {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
         write!(f, "({})", self.cases.iter().map(|c| format!("{:?}", c.0)).join(" | "))
     }
 }
 
-impl<Tk: Token + 'static, A: 'static + MemoAllocator> SwitchReader_<Tk, A> {
+impl<Tk: Token + 'static> SwitchReader<Tk> {
     pub fn new(cases: Vec<Rc<dyn Reader<Tk>>>, policy: Policy, tag: Tag) -> Self {
-        SwitchReader_ {
+        SwitchReader {
             cases: cases.iter().enumerate().map(|t| (t.1.clone(), t.0 as usize)).collect(),
             policy,
             tag,
-            phantom: PhantomData
         }
     }
 
     fn process(&self, to_res: impl Fn(&Rc<dyn Reader<Tk>>) -> ReadingResult<Tk>) -> ReadingResult<Tk> {
-        let results: Vec<(ReadingResult<Tk>, usize)> = self.cases.iter().map(|(c, i)| (to_res(c), *i)).collect();
-        let ongoings: Vec<Case<Tk>> = results.iter().filter_map(|(c, i)| c.ongoing.clone().map(|o| (o, *i))).collect();
+        // a case sharing its reader with an earlier one never succeeds first
+        let results: Vec<(ReadingResult<Tk>, usize)> = self.cases.iter().enumerate()
+            .filter(|&(k, (c, _))| !self.cases[..k].iter().any(|(earlier, _)| Rc::ptr_eq(c, earlier)))
+            .map(|(_, (c, i))| (to_res(c), *i)).collect();
+        let mut ongoings: Vec<Case<Tk>> = results.iter().filter_map(|(c, i)| c.ongoing.clone().map(|o| (o, *i))).collect();
+        let mut i = 1;
+        while i < ongoings.len() {
+            if ongoings[..i].iter().any(|(o, _)| Rc::ptr_eq(o, &ongoings[i].0)) { ongoings.remove(i); } else { i += 1; }
+        }
         let ongoing: Option<Rc<dyn Reader<Tk>>> = if ongoings.is_empty() {
             None
+        } else if ongoings.len() == 1 && self.tag.is_none() {
+            // an untagged switch decided on its case only adds its frame to the case successes
+            let (o, i) = ongoings.pop().unwrap();
+            Some(rc_framed(o, vec![Trace::Switch(i, self.policy)]))
         } else {
-            Some(A::rc(SwitchReader_::<Tk, A> { cases: ongoings, policy: self.policy, tag: self.tag, phantom: PhantomData }, self))
+            Some(rc_reader(SwitchReader { cases: ongoings, policy: self.policy, tag: self.tag }))
         };
         let success = results.iter().find(|(c, _)| c.success.is_some()).map(|(c, i)| (c.success.clone().unwrap(), i));
         ReadingResult {
@@ -59,7 +65,7 @@ impl<Tk: Token + 'static, A: 'static + MemoAllocator> SwitchReader_<Tk, A> {
     }
 }
 
-impl<Tk: Token + 'static, A: MemoAllocator + 'static> Reader<Tk> for SwitchReader_<Tk, A> {
+impl<Tk: Token + 'static> Reader<Tk> for SwitchReader<Tk> {
     fn epsilon(&self, _: &Rc<dyn Reader<Tk>>) -> ReadingResult<Tk> {
         self.process(epsilon)
     }
@@ -67,9 +73,33 @@ impl<Tk: Token + 'static, A: MemoAllocator + 'static> Reader<Tk> for SwitchReade
     fn read(&self, _: &Rc<dyn Reader<Tk>>, token: Tk) -> ReadingResult<Tk> {
         self.process(|r| read(r, token))
     }
+
+    fn ongoing_children(&self) -> Vec<Rc<dyn Reader<Tk>>> {
+        self.cases.iter().map(|(c, _)| c.clone()).collect()
+    }
+
+    fn shape(&self) -> Option<Vec<usize>> {
+        let mut shape = Vec::with_capacity(2 + 2 * self.cases.len());
+        shape.push(self.policy as usize);
+        shape.push(tag_key(self.tag));
+        for (c, i) in &self.cases {
+            shape.push(address(c));
+            shape.push(*i);
+        }
+        Some(shape)
+    }
+
+    fn grammar_children(&self) -> Vec<Rc<dyn Reader<Tk>>> {
+        self.ongoing_children()
+    }
+
+    fn regex(&self) -> Option<Regex> {
+        let cases = self.cases.iter().map(|(c, _)| c.regex()).collect::<Option<Vec<Regex>>>()?;
+        Some(group(Regex::Alt(cases), self.tag))
+    }
 }
 
-impl<Tk: Token, A: MemoAllocator> TreeBuilder for SwitchReader_<Tk, A> {
+impl<Tk: Token> TreeBuilder for SwitchReader<Tk> {
     fn tag(&self) -> Tag {
         self.tag
     }
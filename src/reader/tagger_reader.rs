@@ -18,6 +18,10 @@ impl<Tk: Token + 'static> Reader<Tk> for TaggerReader<Tk> {
     fn read(&self, _: &Rc<dyn Reader<Tk>>, _: Tk) -> ReadingResult<Tk> {
         unimplemented!()
     }
+
+    fn grammar_children(&self) -> Vec<Rc<dyn Reader<Tk>>> {
+        vec![self.reader.clone()]
+    }
 }
 
 impl<Tk: Token> TreeBuilder for TaggerReader<Tk> {
@@ -1,4 +1,6 @@
 use reader::*;
+use regex::group;
+use regex::Regex;
 use std::fmt::Debug;
 use std::fmt::Error;
 use std::fmt::Formatter;
@@ -29,6 +31,14 @@ impl<Tk: Token> Reader<Tk> for TokenReader {
             ongoing: None,
         }
     }
+
+    fn token_sets(&self) -> Vec<Vec<TokenId>> {
+        vec![vec![self.token_ref]]
+    }
+
+    fn regex(&self) -> Option<Regex> {
+        if self.token_ref < 256 { Some(group(Regex::Class(vec![self.token_ref as u8]), self.tag)) } else { None }
+    }
 }
 
 impl TreeBuilder for TokenReader {
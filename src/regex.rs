@@ -0,0 +1,512 @@
+use diagnostics::Diagnostic;
+use reader::conditional_token_reader::ConditionalTokenReader;
+use reader::epsilon_reader::EpsilonReader;
+use reader::list_reader::ListReader;
+use reader::loop_reader::LoopOrdering;
+use reader::loop_reader::LoopReader;
+use reader::optional_reader::OptionalReader;
+use reader::rc_reader;
+use reader::Reader;
+use reader::switch_reader::SwitchReader;
+use reader::token_reader::TokenReader;
+use spans::Span;
+use std::rc::Rc;
+use symbols::SymbolTable;
+use symbols::Tag;
+use traces::Policy;
+
+// regular expressions over bytes, named groups being tagged
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Regex {
+    Empty,
+    // the matched bytes, sorted
+    Class(Vec<u8>),
+    Concat(Vec<Regex>),
+    Alt(Vec<Regex>),
+    Repeat(Box<Regex>, usize, Option<usize>),
+    Group(Box<Regex>, Tag),
+}
+
+#[derive(Clone, Debug)]
+pub struct RegexError {
+    pub at: usize,
+    pub message: String,
+}
+
+impl RegexError {
+    pub fn diagnostic(&self, pattern: &str) -> Diagnostic {
+        let stop = (self.at + 1).min(pattern.len());
+        Diagnostic::error("invalid regular expression".to_string())
+            .with_label(Span::new(self.at, stop.max(self.at)), self.message.clone())
+    }
+}
+
+// the most repetitions a `{n,m}` bound may ask for, each one being a reader
+const MAX_REPETITIONS: usize = 1000;
+
+struct RegexParser<'p> {
+    pattern: &'p [u8],
+    pos: usize,
+    table: &'p mut SymbolTable,
+}
+
+fn class(bytes: impl IntoIterator<Item=u8>) -> Regex {
+    let mut bytes: Vec<u8> = bytes.into_iter().collect();
+    bytes.sort();
+    bytes.dedup();
+    Regex::Class(bytes)
+}
+
+fn complement(bytes: &[u8]) -> Vec<u8> {
+    (0..=255u8).filter(|b| !bytes.contains(b)).collect()
+}
+
+// the bytes of `\d`, `\w` and `\s`, and of their complements
+fn class_escape(c: u8) -> Option<Vec<u8>> {
+    let bytes: Vec<u8> = match c.to_ascii_lowercase() {
+        b'd' => (b'0'..=b'9').collect(),
+        b'w' => (0..=255u8).filter(|b| b.is_ascii_alphanumeric() || *b == b'_').collect(),
+        b's' => b" \t\n\r\x0B\x0C".to_vec(),
+        _ => return None,
+    };
+    Some(if c.is_ascii_uppercase() { complement(&bytes) } else { bytes })
+}
+
+impl<'p> RegexParser<'p> {
+    fn error<T>(&self, message: &str) -> Result<T, RegexError> {
+        Err(RegexError { at: self.pos, message: message.to_string() })
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.pattern.get(self.pos).cloned()
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn alt(&mut self) -> Result<Regex, RegexError> {
+        let mut cases = vec![self.concat()?];
+        while self.eat(b'|') {
+            cases.push(self.concat()?);
+        }
+        Ok(if cases.len() == 1 { cases.pop().unwrap() } else { Regex::Alt(cases) })
+    }
+
+    fn concat(&mut self) -> Result<Regex, RegexError> {
+        let mut elts = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == b'|' || c == b')' { break; }
+            elts.push(self.repeat()?);
+        }
+        Ok(concat(elts))
+    }
+
+    fn repeat(&mut self) -> Result<Regex, RegexError> {
+        let mut regex = self.atom()?;
+        loop {
+            let (min, max) = match self.peek() {
+                Some(b'{') => {
+                    self.pos += 1;
+                    self.bounds()?
+                }
+                Some(c) => {
+                    let bounds = match c {
+                        b'*' => (0, None),
+                        b'+' => (1, None),
+                        b'?' => (0, Some(1)),
+                        _ => return Ok(regex),
+                    };
+                    self.pos += 1;
+                    bounds
+                }
+                None => return Ok(regex),
+            };
+            if self.peek() == Some(b'?') { return self.error("lazy quantifiers are not supported"); }
+            regex = repeat(regex, min, max);
+        }
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        String::from_utf8_lossy(&self.pattern[start..self.pos]).parse().ok()
+    }
+
+    fn count(&mut self) -> Result<usize, RegexError> {
+        match self.number() {
+            Some(count) if count <= MAX_REPETITIONS => Ok(count),
+            Some(_) => self.error(&format!("repetition counts are at most {}", MAX_REPETITIONS)),
+            None => self.error("expected a repetition count"),
+        }
+    }
+
+    // `n}`, `n,}` or `n,m}`
+    fn bounds(&mut self) -> Result<(usize, Option<usize>), RegexError> {
+        let min = self.count()?;
+        let max = if self.eat(b',') {
+            if self.peek() == Some(b'}') { None } else {
+                match self.count()? {
+                    max if max >= min => Some(max),
+                    _ => return self.error("the maximum repetition count is below the minimum"),
+                }
+            }
+        } else {
+            Some(min)
+        };
+        if !self.eat(b'}') { return self.error("expected `}`"); }
+        Ok((min, max))
+    }
+
+    fn atom(&mut self) -> Result<Regex, RegexError> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return self.error("expected an expression"),
+        };
+        match c {
+            b'(' => {
+                self.pos += 1;
+                let tag = self.group_name()?;
+                let regex = self.alt()?;
+                if !self.eat(b')') { return self.error("expected `)`"); }
+                Ok(match tag {
+                    Some(_) => Regex::Group(Box::new(regex), tag),
+                    None => regex,
+                })
+            }
+            b'[' => {
+                self.pos += 1;
+                self.class()
+            }
+            b'.' => {
+                self.pos += 1;
+                Ok(class(complement(b"\n")))
+            }
+            b'\\' => {
+                self.pos += 1;
+                self.escape().map(class)
+            }
+            b'*' | b'+' | b'?' | b'{' => self.error("nothing to repeat"),
+            b'^' | b'$' => self.error("anchors are not supported"),
+            _ => {
+                // the bytes of a whole UTF-8 character
+                let len = match c {
+                    0xC0..=0xDF => 2,
+                    0xE0..=0xEF => 3,
+                    0xF0..=0xF7 => 4,
+                    _ => 1,
+                }.min(self.pattern.len() - self.pos);
+                let bytes = &self.pattern[self.pos..self.pos + len];
+                self.pos += len;
+                Ok(if len == 1 { class(bytes.to_vec()) } else { Regex::Concat(bytes.iter().map(|b| class(vec![*b])).collect()) })
+            }
+        }
+    }
+
+    // after `(`: `?:` for a group without name, `?<name>` or `?P<name>` for a tagged one
+    fn group_name(&mut self) -> Result<Tag, RegexError> {
+        if !self.eat(b'?') { return Ok(None); }
+        if self.eat(b':') { return Ok(None); }
+        self.eat(b'P');
+        if !self.eat(b'<') { return self.error("expected `:` or a group name"); }
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == b'_') {
+            self.pos += 1;
+        }
+        if self.pos == start { return self.error("expected a group name"); }
+        let name = String::from_utf8_lossy(&self.pattern[start..self.pos]).to_string();
+        if !self.eat(b'>') { return self.error("expected `>`"); }
+        Ok(Some(self.table.get(&name)))
+    }
+
+    // after `\`
+    fn escape(&mut self) -> Result<Vec<u8>, RegexError> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return self.error("expected an escaped character"),
+        };
+        self.pos += 1;
+        if let Some(bytes) = class_escape(c) { return Ok(bytes); }
+        let byte = match c {
+            b'n' => b'\n',
+            b't' => b'\t',
+            b'r' => b'\r',
+            b'0' => 0,
+            b'x' => {
+                let hex = self.pattern.get(self.pos..self.pos + 2).and_then(|hex| u8::from_str_radix(&String::from_utf8_lossy(hex), 16).ok());
+                match hex {
+                    Some(byte) => {
+                        self.pos += 2;
+                        byte
+                    }
+                    None => return self.error("expected two hexadecimal digits"),
+                }
+            }
+            c if c.is_ascii_alphanumeric() => {
+                self.pos -= 1;
+                return self.error("unknown escape");
+            }
+            c => c,
+        };
+        Ok(vec![byte])
+    }
+
+    // after `[`
+    fn class(&mut self) -> Result<Regex, RegexError> {
+        let negated = self.eat(b'^');
+        let mut bytes = Vec::new();
+        let mut first = true;
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return self.error("expected `]`"),
+            };
+            if c == b']' && !first { break; }
+            first = false;
+            let lower = self.class_byte()?;
+            let lower = match lower {
+                Ok(byte) => byte,
+                Err(escaped) => {
+                    bytes.extend(escaped);
+                    continue;
+                }
+            };
+            if self.peek() == Some(b'-') && self.pattern.get(self.pos + 1).is_some_and(|c| *c != b']') {
+                self.pos += 1;
+                match self.class_byte()? {
+                    Ok(upper) if upper >= lower => bytes.extend(lower..=upper),
+                    Ok(_) => return self.error("invalid range"),
+                    Err(_) => return self.error("invalid range"),
+                }
+            } else {
+                bytes.push(lower);
+            }
+        }
+        self.pos += 1;
+        Ok(class(if negated { complement(&bytes) } else { bytes }))
+    }
+
+    // a byte of a class, or the bytes of a class escape
+    fn class_byte(&mut self) -> Result<Result<u8, Vec<u8>>, RegexError> {
+        let c = self.peek().unwrap();
+        if c >= 0x80 { return self.error("non-ASCII characters in classes are not supported"); }
+        self.pos += 1;
+        if c != b'\\' { return Ok(Ok(c)); }
+        let escaped = self.escape()?;
+        Ok(if escaped.len() == 1 { Ok(escaped[0]) } else { Err(escaped) })
+    }
+}
+
+pub fn parse_regex(table: &mut SymbolTable, pattern: &str) -> Result<Regex, RegexError> {
+    let mut parser = RegexParser { pattern: pattern.as_bytes(), pos: 0, table };
+    let regex = parser.alt()?;
+    if parser.pos < parser.pattern.len() { return parser.error("unmatched `)`"); }
+    Ok(regex)
+}
+
+fn concat(mut elts: Vec<Regex>) -> Regex {
+    match elts.len() {
+        0 => Regex::Empty,
+        1 => elts.pop().unwrap(),
+        _ => Regex::Concat(elts),
+    }
+}
+
+fn alt(mut cases: Vec<Regex>) -> Option<Regex> {
+    match cases.len() {
+        0 => None,
+        1 => cases.pop(),
+        _ => Some(Regex::Alt(cases)),
+    }
+}
+
+// `regex{min,max}`, its body being rewritten so that it cannot match the empty word, and nested
+// unbounded repetitions being merged: readers never meet two ways to repeat the same bytes
+fn repeat(regex: Regex, min: usize, max: Option<usize>) -> Regex {
+    if max == Some(0) { return Regex::Empty; }
+    let (regex, min) = if regex.nullable() {
+        match regex.non_empty() {
+            Some(regex) => (regex, 0),
+            None => return Regex::Empty,
+        }
+    } else {
+        (regex, min)
+    };
+    match (regex, max) {
+        (Regex::Repeat(body, inner_min, None), None) if inner_min <= 1 => Regex::Repeat(body, inner_min * min, None),
+        (regex, max) => Regex::Repeat(Box::new(regex), min, max),
+    }
+}
+
+pub fn group(regex: Regex, tag: Tag) -> Regex {
+    match tag {
+        Some(_) => Regex::Group(Box::new(regex), tag),
+        None => regex,
+    }
+}
+
+// wraps the readers which cannot be tagged themselves
+fn tagged(reader: Rc<dyn Reader<u8>>, tag: Tag) -> Rc<dyn Reader<u8>> {
+    match tag {
+        Some(_) => rc_reader(ListReader::new(vec![reader], tag)),
+        None => reader,
+    }
+}
+
+impl Regex {
+    fn nullable(&self) -> bool {
+        match *self {
+            Regex::Empty => true,
+            Regex::Class(_) => false,
+            Regex::Concat(ref elts) => elts.iter().all(Regex::nullable),
+            Regex::Alt(ref cases) => cases.iter().any(Regex::nullable),
+            Regex::Repeat(ref regex, min, _) => min == 0 || regex.nullable(),
+            Regex::Group(ref regex, _) => regex.nullable(),
+        }
+    }
+
+    // the regex matching the non-empty words of this one, if any
+    fn non_empty(&self) -> Option<Regex> {
+        if !self.nullable() { return Some(self.clone()); }
+        match *self {
+            Regex::Empty | Regex::Class(_) => None,
+            // the words whose first non-empty part is read by each element
+            Regex::Concat(ref elts) => {
+                let cases: Vec<Regex> = (0..elts.len()).filter_map(|i| {
+                    let first = elts[i].non_empty()?;
+                    Some(concat(Some(first).into_iter().chain(elts[i + 1..].iter().cloned()).collect()))
+                }).collect();
+                alt(cases)
+            }
+            Regex::Alt(ref cases) => {
+                let cases: Vec<Regex> = cases.iter().filter_map(Regex::non_empty).collect();
+                alt(cases)
+            }
+            Regex::Repeat(ref regex, _, max) => match repeat(regex.non_empty()?, 1, max) {
+                Regex::Empty => None,
+                regex => Some(regex),
+            },
+            Regex::Group(ref regex, tag) => Some(Regex::Group(Box::new(regex.non_empty()?), tag)),
+        }
+    }
+
+    pub fn reader(&self, tag: Tag) -> Rc<dyn Reader<u8>> {
+        match *self {
+            Regex::Empty => tagged(rc_reader(EpsilonReader), tag),
+            Regex::Class(ref bytes) if bytes.len() == 1 => rc_reader(TokenReader { token_ref: bytes[0] as usize, tag }),
+            Regex::Class(ref bytes) => rc_reader(ConditionalTokenReader::include(bytes.clone(), 256, tag)),
+            Regex::Concat(ref elts) => rc_reader(ListReader::new(elts.iter().map(|elt| elt.reader(None)).collect(), tag)),
+            Regex::Alt(ref cases) => rc_reader(SwitchReader::new(cases.iter().map(|case| case.reader(None)).collect(), Policy::Longest, tag)),
+            Regex::Repeat(ref regex, _, _) if **regex == Regex::Empty => tagged(rc_reader(EpsilonReader), tag),
+            Regex::Repeat(ref regex, min, max) => {
+                let reader = regex.reader(None);
+                let mut elts = vec![reader.clone(); min];
+                match max {
+                    None => elts.push(rc_reader(LoopReader::new(reader, Policy::Longest, LoopOrdering::Increasing, None))),
+                    Some(max) => if max > min {
+                        // r{0,n} is (r(r(...)?)?)?
+                        let mut optional = rc_reader(OptionalReader::new(reader.clone()));
+                        for _ in min + 1..max {
+                            optional = rc_reader(OptionalReader::new(rc_reader(ListReader::new(vec![reader.clone(), optional], None))));
+                        }
+                        elts.push(optional);
+                    },
+                }
+                match elts.len() {
+                    0 => tagged(rc_reader(EpsilonReader), tag),
+                    1 => tagged(elts.pop().unwrap(), tag),
+                    _ => rc_reader(ListReader::new(elts, tag)),
+                }
+            }
+            Regex::Group(ref regex, group_tag) => match tag {
+                Some(_) => tagged(regex.reader(group_tag), tag),
+                None => regex.reader(group_tag),
+            },
+        }
+    }
+}
+
+pub fn regex_reader(table: &mut SymbolTable, pattern: &str, tag: Tag) -> Result<Rc<dyn Reader<u8>>, RegexError> {
+    Ok(parse_regex(table, pattern)?.reader(tag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::parse;
+    use spans::LineIndex;
+    use trees::tree_from_trace_at;
+
+    fn matches(pattern: &str, input: &str) -> bool {
+        let reader = regex_reader(&mut SymbolTable::new(), pattern, None).unwrap();
+        let res = parse(input.bytes(), &reader);
+        res.success.is_some() && res.is_complete() && res.nb_tokens_read == input.len()
+    }
+
+    #[test]
+    fn readers_match_the_language_of_their_regex() {
+        let cases = vec![
+            ("ab|c", vec!["ab", "c"], vec!["", "a", "abc"]),
+            ("a(bc)*d", vec!["ad", "abcd", "abcbcd"], vec!["abd", "abcbd"]),
+            ("a+b?", vec!["a", "aab"], vec!["", "b", "abb"]),
+            ("x{2,3}", vec!["xx", "xxx"], vec!["x", "xxxx"]),
+            ("x{2,}y{2}", vec!["xxyy", "xxxxyy"], vec!["xyy", "xxy"]),
+            ("[a-c\\d]+", vec!["a1c", "9"], vec!["d", "a-"]),
+            ("[^a-z]\\.", vec!["A.", "..", "1."], vec!["a.", "Ab"]),
+            ("\\x41\\t\\w\\s", vec!["A\t_ "], vec!["A\t- "]),
+            ("-?(0|[1-9][0-9]*)(\\.[0-9]+)?", vec!["0", "-12.5", "7.0"], vec!["01", "1.", "-"]),
+            ("(a*)*", vec!["", "a", "aa"], vec!["b", "ab"]),
+            ("(|a)+", vec!["", "a", "aa"], vec!["b", "ab"]),
+            ("(a?){2}(b+)+", vec!["b", "aabb"], vec!["aaab", "a"]),
+        ];
+        for (pattern, accepted, rejected) in cases {
+            for input in accepted { assert!(matches(pattern, input), "{} should match {:?}", pattern, input); }
+            for input in rejected { assert!(!matches(pattern, input), "{} should not match {:?}", pattern, input); }
+        }
+    }
+
+    #[test]
+    fn named_groups_are_tagged() {
+        let mut table = SymbolTable::new();
+        let reader = regex_reader(&mut table, "(?<key>[a-z]+)=(?P<value>[0-9]+)(?:;)?", None).unwrap();
+        let input = b"ab=12;";
+        let trace = parse(input.iter().cloned(), &reader).success.unwrap();
+        let tree = tree_from_trace_at(reader.as_tree_builder(), &trace, input, 0);
+        let parts: Vec<(String, Span)> = tree.tagged().map(|part| (table.val(part.tag().unwrap()), part.span().unwrap())).collect();
+        assert_eq!(parts, vec![("key".to_string(), Span::new(0, 2)), ("value".to_string(), Span::new(3, 5))]);
+    }
+
+    #[test]
+    fn errors_are_located() {
+        let cases = vec![
+            ("a(b", 3, "expected `)`"),
+            ("a)", 1, "unmatched `)`"),
+            ("*a", 0, "nothing to repeat"),
+            ("a{3,2}", 5, "the maximum repetition count is below the minimum"),
+            ("a{2,1001}", 8, "repetition counts are at most 1000"),
+            ("^a", 0, "anchors are not supported"),
+            ("a*?", 2, "lazy quantifiers are not supported"),
+            ("[z-a]", 4, "invalid range"),
+            ("(?<>a)", 3, "expected a group name"),
+        ];
+        for (pattern, at, message) in cases {
+            let err = parse_regex(&mut SymbolTable::new(), pattern).unwrap_err();
+            assert_eq!((err.at, &err.message[..]), (at, message), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn errors_are_rendered_on_the_pattern() {
+        let pattern = "a)";
+        let err = parse_regex(&mut SymbolTable::new(), pattern).unwrap_err();
+        let rendered = err.diagnostic(pattern).render(pattern, &LineIndex::new(pattern), false);
+        assert_eq!(rendered, "error: invalid regular expression\n --> 1:2\n  |\n1 | a)\n  |  ^ unmatched `)`\n");
+    }
+}
@@ -0,0 +1,195 @@
+use list::List;
+use list::Stack;
+use reader::epsilon;
+use reader::read;
+use reader::Reader;
+use reader::Token;
+use reader::TokenId;
+use std::rc::Rc;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Edit {
+    Insert(usize, TokenId),
+    Delete(usize, TokenId),
+}
+
+impl Edit {
+    pub fn position(&self) -> usize {
+        match *self {
+            Edit::Insert(pos, _) => pos,
+            Edit::Delete(pos, _) => pos,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EditScript {
+    pub edits: Vec<Edit>,
+    pub cost: usize,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct RepairBudget {
+    pub max_cost: usize,
+    pub max_states: usize,
+    pub max_scripts: usize,
+    pub window: usize,
+}
+
+impl Default for RepairBudget {
+    fn default() -> Self {
+        RepairBudget { max_cost: 3, max_states: 10_000, max_scripts: 5, window: 3 }
+    }
+}
+
+struct State<Tk: Token> {
+    pos: usize,
+    ongoing: Option<Rc<dyn Reader<Tk>>>,
+    accepting: bool,
+    edits: Rc<List<Edit, ()>>,
+}
+
+impl<Tk: Token> State<Tk> {
+    fn edit(&self, pos: usize, ongoing: Option<Rc<dyn Reader<Tk>>>, accepting: bool, edit: Edit) -> Self {
+        State { pos, ongoing, accepting, edits: self.edits.push(edit) }
+    }
+
+    fn just_inserted(&self) -> bool {
+        match *self.edits {
+            List::Cons(Edit::Insert(pos, _), _) => pos == self.pos,
+            _ => false,
+        }
+    }
+
+    fn script(&self, cost: usize) -> EditScript {
+        let mut edits: Vec<Edit> = self.edits.iter().cloned().collect();
+        edits.reverse();
+        EditScript { edits, cost }
+    }
+}
+
+// Searches the cheapest sequences of token insertions and deletions that turn `tokens` into a
+// complete parse of `reader`. Edits are only tried from `budget.window` tokens before the first
+// failing token; scripts are ranked by cost, then by the order in which they were found.
+pub fn repairs<Tk: Token>(reader: &Rc<dyn Reader<Tk>>, tokens: &[Tk], insertable: &[Tk], budget: &RepairBudget) -> Vec<EditScript> {
+    let eps = epsilon(reader);
+    let mut prefix = vec![(eps.ongoing, eps.success.is_some())];
+    for tk in tokens {
+        let res = match prefix.last().unwrap().0 {
+            Some(ref ongoing) => read(ongoing, *tk),
+            None => break,
+        };
+        if res.success.is_none() && res.ongoing.is_none() { break; }
+        prefix.push((res.ongoing, res.success.is_some()));
+    }
+    let error_pos = prefix.len() - 1;
+    if error_pos == tokens.len() && prefix[error_pos].1 { return Vec::new(); }
+    let start = error_pos.saturating_sub(budget.window);
+    let (ongoing, accepting) = prefix.swap_remove(start);
+    let mut layers: Vec<Vec<State<Tk>>> = (0..budget.max_cost + 1).map(|_| Vec::new()).collect();
+    layers[0].push(State { pos: start, ongoing, accepting, edits: Rc::new(List::Nil(())) });
+    let mut scripts: Vec<EditScript> = Vec::new();
+    let mut nb_states = 0;
+    for cost in 0..budget.max_cost + 1 {
+        let layer = ::std::mem::take(&mut layers[cost]);
+        for mut state in layer {
+            loop {
+                nb_states += 1;
+                if nb_states > budget.max_states || scripts.len() >= budget.max_scripts { return scripts; }
+                if state.pos == tokens.len() && state.accepting && cost > 0 {
+                    let script = state.script(cost);
+                    if !scripts.contains(&script) { scripts.push(script); }
+                }
+                if cost < budget.max_cost {
+                    if state.pos < tokens.len() && !state.just_inserted() {
+                        let deleted = tokens[state.pos];
+                        let next = state.edit(state.pos + 1, state.ongoing.clone(), state.accepting, Edit::Delete(state.pos, deleted.id()));
+                        layers[cost + 1].push(next);
+                    }
+                    if let Some(ref ongoing) = state.ongoing {
+                        for tk in insertable {
+                            let res = read(ongoing, *tk);
+                            if res.success.is_some() || res.ongoing.is_some() {
+                                let next = state.edit(state.pos, res.ongoing, res.success.is_some(), Edit::Insert(state.pos, tk.id()));
+                                layers[cost + 1].push(next);
+                            }
+                        }
+                    }
+                }
+                if state.pos == tokens.len() { break; }
+                let res = match state.ongoing {
+                    Some(ref ongoing) => read(ongoing, tokens[state.pos]),
+                    None => break,
+                };
+                if res.success.is_none() && res.ongoing.is_none() { break; }
+                state = State { pos: state.pos + 1, ongoing: res.ongoing, accepting: res.success.is_some(), edits: state.edits };
+            }
+        }
+    }
+    scripts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_grammars::*;
+
+    fn fix(tokens: &[TokenId], budget: &RepairBudget) -> Vec<EditScript> {
+        repairs(&grammar(), tokens, &[OPEN, CLOSE, A], budget)
+    }
+
+    #[test]
+    fn complete_input_needs_no_repair() {
+        assert!(fix(&[OPEN, A, CLOSE], &RepairBudget::default()).is_empty());
+    }
+
+    #[test]
+    fn missing_token_is_inserted() {
+        let scripts = fix(&[OPEN, A], &RepairBudget::default());
+        assert_eq!(scripts[0], EditScript { edits: vec![Edit::Insert(2, CLOSE)], cost: 1 });
+    }
+
+    #[test]
+    fn extra_token_is_deleted() {
+        let scripts = fix(&[OPEN, A, CLOSE, A], &RepairBudget::default());
+        assert_eq!(scripts[0], EditScript { edits: vec![Edit::Delete(3, A)], cost: 1 });
+    }
+
+    #[test]
+    fn scripts_are_ranked_by_cost() {
+        let scripts = fix(&[CLOSE, A], &RepairBudget::default());
+        assert!(!scripts.is_empty());
+        assert!(scripts.windows(2).all(|pair| pair[0].cost <= pair[1].cost));
+        assert!(scripts.iter().all(|script| script.cost == script.edits.len()));
+    }
+
+    #[test]
+    fn max_cost_bounds_the_scripts() {
+        let budget = RepairBudget { max_cost: 1, ..RepairBudget::default() };
+        // at least an insertion of `(` and one of `)` are needed
+        assert!(fix(&[A], &budget).is_empty());
+        let budget = RepairBudget { max_cost: 2, ..RepairBudget::default() };
+        assert_eq!(fix(&[A], &budget)[0].cost, 2);
+    }
+
+    #[test]
+    fn max_scripts_bounds_the_scripts() {
+        let budget = RepairBudget { max_scripts: 1, ..RepairBudget::default() };
+        assert_eq!(fix(&[OPEN, A, A, A], &budget).len(), 1);
+    }
+
+    #[test]
+    fn max_states_bounds_the_search() {
+        let budget = RepairBudget { max_states: 1, ..RepairBudget::default() };
+        assert!(fix(&[OPEN, A], &budget).is_empty());
+    }
+
+    #[test]
+    fn edits_stay_in_the_window() {
+        let tokens = [OPEN, A, A, A, A, A, OPEN];
+        let budget = RepairBudget { window: 2, ..RepairBudget::default() };
+        let scripts = fix(&tokens, &budget);
+        assert!(!scripts.is_empty());
+        assert!(scripts.iter().all(|script| script.edits.iter().all(|edit| edit.position() >= 6 - 2)));
+    }
+}
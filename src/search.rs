@@ -0,0 +1,168 @@
+use list::List;
+use reader::epsilon;
+use reader::read;
+use reader::Reader;
+use reader::Token;
+use reader::memoization::Lru;
+use reader::memoization::rc_memo_states;
+use std::cmp::Ordering;
+use std::rc::Rc;
+use traces::Trace;
+use traces::TraceEnding;
+use trees::cmp_readings;
+
+// among the matches starting the leftmost, the longest one, or the first one a backtracking
+// matcher would find, see `trees::cmp_readings`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MatchKind {
+    LeftmostLongest,
+    LeftmostFirst,
+}
+
+// non-overlapping non-empty matches of a reader, as (start, stop, trace)
+pub struct Matches<'t, Tk: Token + 't> {
+    reader: Rc<dyn Reader<Tk>>,
+    tokens: &'t [Tk],
+    kind: MatchKind,
+    pos: usize,
+}
+
+// the reads kept by each state: threads reading the same tokens from their starts share their
+// states, whatever their positions
+const SEARCH_MEMO_CAPACITY: usize = 4;
+
+pub fn find_iter<'t, Tk: Token + 'static>(reader: &Rc<dyn Reader<Tk>>, tokens: &'t [Tk], kind: MatchKind) -> Matches<'t, Tk> {
+    Matches { reader: rc_memo_states(reader.clone(), Lru::new(SEARCH_MEMO_CAPACITY)), tokens, kind, pos: 0 }
+}
+
+type Thread<Tk> = (usize, Rc<dyn Reader<Tk>>);
+
+impl<'t, Tk: Token> Iterator for Matches<'t, Tk> {
+    type Item = (usize, usize, Rc<List<Trace, TraceEnding>>);
+
+    fn next(&mut self) -> Option<(usize, usize, Rc<List<Trace, TraceEnding>>)> {
+        // threads are ordered by start, and never in the same state at once: the states carry the
+        // traces of the tokens read
+        let mut threads: Vec<Thread<Tk>> = Vec::new();
+        let mut best: Option<(usize, usize, Rc<List<Trace, TraceEnding>>)> = None;
+        let mut pos = self.pos;
+        loop {
+            if best.is_none() && pos < self.tokens.len() {
+                if let Some(ongoing) = epsilon(&self.reader).ongoing {
+                    threads.push((pos, ongoing));
+                }
+            }
+            if threads.is_empty() || pos == self.tokens.len() { break; }
+            let tk = self.tokens[pos];
+            let mut next_threads: Vec<Thread<Tk>> = Vec::new();
+            for (start, ongoing) in threads {
+                if best.as_ref().is_some_and(|&(best_start, _, _)| start > best_start) { break; }
+                let res = read(&ongoing, tk);
+                if let Some(success) = res.success {
+                    let better = match best {
+                        Some((best_start, _, ref best_success)) if best_start == start && self.kind == MatchKind::LeftmostFirst =>
+                            cmp_readings(self.reader.as_tree_builder(), &success, best_success) == Ordering::Less,
+                        _ => true,
+                    };
+                    if better { best = Some((start, pos + 1, success)); }
+                }
+                if let Some(ongoing) = res.ongoing {
+                    next_threads.push((start, ongoing));
+                }
+            }
+            if let Some((best_start, _, _)) = best {
+                next_threads.retain(|&(start, _)| start <= best_start);
+            }
+            threads = next_threads;
+            pos += 1;
+        }
+        match best {
+            Some(found) => {
+                self.pos = found.1;
+                Some(found)
+            }
+            None => {
+                self.pos = self.tokens.len();
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reader::conditional_token_reader::ConditionalTokenReader;
+    use reader::list_reader::ListReader;
+    use reader::loop_reader::LoopOrdering;
+    use reader::loop_reader::LoopReader;
+    use reader::rc_reader;
+    use reader::switch_reader::SwitchReader;
+    use reader::token_reader::TokenReader;
+    use super::*;
+    use traces::Policy;
+
+    fn word(word: &str) -> Rc<dyn Reader<u8>> {
+        rc_reader(ListReader::new(word.bytes().map(|b| rc_reader(TokenReader { token_ref: b as usize, tag: None })).collect(), None))
+    }
+
+    fn either(words: Vec<&str>) -> Rc<dyn Reader<u8>> {
+        rc_reader(SwitchReader::new(words.into_iter().map(word).collect(), Policy::Longest, None))
+    }
+
+    fn star(reader: Rc<dyn Reader<u8>>) -> Rc<dyn Reader<u8>> {
+        rc_reader(LoopReader::new(reader, Policy::Longest, LoopOrdering::Increasing, None))
+    }
+
+    fn plus(reader: Rc<dyn Reader<u8>>) -> Rc<dyn Reader<u8>> {
+        rc_reader(ListReader::new(vec![reader.clone(), star(reader)], None))
+    }
+
+    fn matches(reader: Rc<dyn Reader<u8>>, text: &str, kind: MatchKind) -> Vec<(usize, usize)> {
+        find_iter(&reader, text.as_bytes(), kind).map(|(start, stop, _)| (start, stop)).collect()
+    }
+
+    #[test]
+    fn longest_matches_are_kept() {
+        assert_eq!(matches(plus(word("a")), "baaaba", MatchKind::LeftmostLongest), vec![(1, 4), (5, 6)]);
+        assert_eq!(matches(word("aa"), "aaaaa", MatchKind::LeftmostLongest), vec![(0, 2), (2, 4)]);
+    }
+
+    #[test]
+    fn first_matches_are_kept() {
+        assert_eq!(matches(either(vec!["ab", "a"]), "ab", MatchKind::LeftmostFirst), vec![(0, 2)]);
+        assert_eq!(matches(either(vec!["a", "ab"]), "ab", MatchKind::LeftmostFirst), vec![(0, 1)]);
+        assert_eq!(matches(plus(word("a")), "baaaba", MatchKind::LeftmostFirst), vec![(1, 4), (5, 6)]);
+        // `(a|ab)*` stops once its first iteration reads `a`
+        assert_eq!(matches(star(either(vec!["a", "ab"])), "abab", MatchKind::LeftmostFirst), vec![(0, 1), (2, 3)]);
+        assert_eq!(matches(star(either(vec!["ab", "a"])), "abab", MatchKind::LeftmostFirst), vec![(0, 4)]);
+    }
+
+    #[test]
+    fn decreasing_loops_match_lazily() {
+        let lazy = rc_reader(LoopReader::new(word("b"), Policy::Longest, LoopOrdering::Decreasing, None));
+        let reader = rc_reader(ListReader::new(vec![word("a"), lazy, word("b")], None));
+        assert_eq!(matches(reader.clone(), "abbb", MatchKind::LeftmostFirst), vec![(0, 2)]);
+        assert_eq!(matches(reader, "abbb", MatchKind::LeftmostLongest), vec![(0, 4)]);
+    }
+
+    #[test]
+    fn leftmost_matches_win() {
+        assert_eq!(matches(either(vec!["ab", "bcde"]), "abcde", MatchKind::LeftmostLongest), vec![(0, 2)]);
+        assert_eq!(matches(either(vec!["abcd", "bc"]), "abce", MatchKind::LeftmostFirst), vec![(1, 3)]);
+    }
+
+    #[test]
+    fn empty_matches_are_skipped() {
+        assert_eq!(matches(star(word("a")), "bab", MatchKind::LeftmostLongest), vec![(1, 2)]);
+    }
+
+    #[test]
+    fn repeated_tokens_share_their_states() {
+        let digit = rc_reader(ConditionalTokenReader::include((b'0'..b'9' + 1).collect(), 256, None));
+        let number = plus(digit);
+        let reader = rc_reader(ListReader::new(vec![number.clone(), word("-"), number], None));
+        let traces: Vec<_> = find_iter(&reader, "1-2 1-2".as_bytes(), MatchKind::LeftmostLongest).map(|(_, _, trace)| trace).collect();
+        assert_eq!(traces.len(), 2);
+        assert!(Rc::ptr_eq(&traces[0], &traces[1]));
+    }
+}
@@ -0,0 +1,140 @@
+use std::io;
+use std::io::BufRead;
+
+// bytes to lex, addressed by their global offset in the input
+pub trait Source {
+    // offset of the first buffered byte, and the buffered bytes
+    fn buffer(&self) -> (usize, &[u8]);
+    // buffers more bytes, false at the end of the input
+    fn fill(&mut self) -> bool;
+    // bytes before `offset` won't be read anymore
+    fn release(&mut self, offset: usize);
+    // the error which ended the input early, if any
+    fn take_error(&mut self) -> Option<io::Error> {
+        None
+    }
+
+    fn byte_at(&mut self, offset: usize) -> Option<u8> {
+        loop {
+            {
+                let (start, bytes) = self.buffer();
+                if offset - start < bytes.len() { return Some(bytes[offset - start]); }
+            }
+            if !self.fill() { return None; }
+        }
+    }
+}
+
+// also covers memory-mapped files
+impl<'a> Source for &'a [u8] {
+    fn buffer(&self) -> (usize, &[u8]) {
+        (0, self)
+    }
+
+    fn fill(&mut self) -> bool {
+        false
+    }
+
+    fn release(&mut self, _: usize) {}
+}
+
+// sliding buffer over a reader, only keeping the bytes not released yet
+pub struct ReadSource<R: BufRead> {
+    reader: R,
+    // the bytes before `start` are released, and dropped once they fill half of the buffer
+    buffer: Vec<u8>,
+    start: usize,
+    offset: usize,
+    eof: bool,
+    error: Option<io::Error>,
+}
+
+impl<R: BufRead> ReadSource<R> {
+    pub fn new(reader: R) -> Self {
+        ReadSource { reader, buffer: Vec::new(), start: 0, offset: 0, eof: false, error: None }
+    }
+}
+
+impl<R: BufRead> Source for ReadSource<R> {
+    fn buffer(&self) -> (usize, &[u8]) {
+        (self.offset, &self.buffer[self.start..])
+    }
+
+    fn fill(&mut self) -> bool {
+        if self.eof { return false; }
+        let nb_read = loop {
+            match self.reader.fill_buf() {
+                Ok(bytes) => {
+                    self.buffer.extend_from_slice(bytes);
+                    break bytes.len();
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    self.error = Some(err);
+                    self.eof = true;
+                    return false;
+                }
+            }
+        };
+        self.reader.consume(nb_read);
+        self.eof = nb_read == 0;
+        !self.eof
+    }
+
+    fn release(&mut self, offset: usize) {
+        if offset > self.offset {
+            self.start += (offset - self.offset).min(self.buffer.len() - self.start);
+            self.offset = offset;
+            if self.start > self.buffer.len() / 2 {
+                self.buffer.drain(..self.start);
+                self.start = 0;
+            }
+        }
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+}
+
+pub struct SourceBytes<'s, S: Source + 's> {
+    source: &'s mut S,
+    offset: usize,
+}
+
+impl<'s, S: Source> SourceBytes<'s, S> {
+    pub fn new(source: &'s mut S, offset: usize) -> Self {
+        SourceBytes { source, offset }
+    }
+}
+
+impl<'s, S: Source> Iterator for SourceBytes<'s, S> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.source.byte_at(self.offset)?;
+        self.offset += 1;
+        Some(byte)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn released_bytes_keep_their_offsets() {
+        let mut source = ReadSource::new(io::BufReader::with_capacity(3, &b"abcdefghij"[..]));
+        assert_eq!(source.byte_at(4), Some(b'e'));
+        source.release(2);
+        assert_eq!(source.buffer(), (2, &b"cdef"[..]));
+        source.release(4);
+        assert_eq!(source.buffer(), (4, &b"ef"[..]));
+        assert_eq!(source.buffer.len(), 2);
+        assert_eq!(source.byte_at(9), Some(b'j'));
+        assert_eq!(source.byte_at(10), None);
+        source.release(10);
+        assert_eq!(source.buffer(), (10, &b""[..]));
+        assert!(source.take_error().is_none());
+    }
+}
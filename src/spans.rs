@@ -0,0 +1,158 @@
+use lexer;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fmt::Error;
+use std::fmt::Formatter;
+use std::rc::Rc;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub stop: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, stop: usize) -> Self {
+        Span { start, stop }
+    }
+
+    pub fn cover(&self, other: Span) -> Span {
+        Span { start: self.start.min(other.start), stop: self.stop.max(other.stop) }
+    }
+}
+
+pub trait Spanned {
+    fn span(&self) -> Span;
+}
+
+impl Spanned for lexer::Token {
+    fn span(&self) -> Span {
+        Span { start: self.start, stop: self.stop }
+    }
+}
+
+impl<T: Spanned> Spanned for Rc<T> {
+    fn span(&self) -> Span {
+        self.as_ref().span()
+    }
+}
+
+impl<'a, T: Spanned> Spanned for &'a T {
+    fn span(&self) -> Span {
+        (*self).span()
+    }
+}
+
+// zero-based, columns are counted in chars
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LineCol {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Display for LineCol {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "{}:{}", self.line + 1, self.col + 1)
+    }
+}
+
+pub struct LineIndex {
+    lines: Vec<Span>,
+    // byte column and byte length of multi-byte chars, by line
+    wide_chars: HashMap<usize, Vec<(usize, usize)>>,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let mut lines = Vec::new();
+        let mut wide_chars = HashMap::new();
+        let mut line_start = 0;
+        let mut chars = text.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\n' | '\r' => {
+                    lines.push(Span::new(line_start, i));
+                    line_start = i + 1;
+                    if c == '\r' && chars.peek().map(|&(_, next)| next) == Some('\n') {
+                        chars.next();
+                        line_start += 1;
+                    }
+                }
+                _ if c.len_utf8() > 1 => {
+                    wide_chars.entry(lines.len()).or_insert_with(Vec::new).push((i - line_start, c.len_utf8()));
+                }
+                _ => {}
+            }
+        }
+        lines.push(Span::new(line_start, text.len()));
+        LineIndex { lines, wide_chars }
+    }
+
+    pub fn nb_lines(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn line_span(&self, line: usize) -> Span {
+        self.lines[line]
+    }
+
+    pub fn line_col(&self, offset: usize) -> LineCol {
+        let line = match self.lines.binary_search_by_key(&offset, |span| span.start) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let byte_col = offset.min(self.lines[line].stop) - self.lines[line].start;
+        let col = match self.wide_chars.get(&line) {
+            Some(wide) => wide.iter()
+                .take_while(|&&(col, _)| col < byte_col)
+                .fold(byte_col, |col, &(wide_col, len)| col - (len - 1).min(byte_col - wide_col)),
+            None => byte_col,
+        };
+        LineCol { line, col }
+    }
+
+    pub fn offset(&self, line_col: LineCol) -> Option<usize> {
+        let span = *self.lines.get(line_col.line)?;
+        let mut offset = span.start + line_col.col;
+        if let Some(wide) = self.wide_chars.get(&line_col.line) {
+            for &(col, len) in wide {
+                if span.start + col >= offset { break; }
+                offset += len - 1;
+            }
+        }
+        if offset <= span.stop { Some(offset) } else { None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_end_at_any_newline() {
+        let index = LineIndex::new("a\nbc\r\nd\re");
+        assert_eq!(index.nb_lines(), 4);
+        assert_eq!(index.line_span(1), Span::new(2, 4));
+        assert_eq!(index.line_col(6), LineCol { line: 2, col: 0 });
+        assert_eq!(index.line_col(8), LineCol { line: 3, col: 0 });
+    }
+
+    #[test]
+    fn columns_count_chars() {
+        let text = "é=ü\nx";
+        let index = LineIndex::new(text);
+        assert_eq!(index.line_col(text.find('=').unwrap()), LineCol { line: 0, col: 1 });
+        assert_eq!(index.line_col(text.find('ü').unwrap()), LineCol { line: 0, col: 2 });
+        assert_eq!(index.line_col(text.find('x').unwrap()), LineCol { line: 1, col: 0 });
+    }
+
+    #[test]
+    fn offsets_round_trip() {
+        let text = "aé\r\nüb\nc";
+        let index = LineIndex::new(text);
+        for (offset, _) in text.char_indices().filter(|&(_, c)| c != '\n' && c != '\r') {
+            assert_eq!(index.offset(index.line_col(offset)), Some(offset));
+        }
+        assert_eq!(index.offset(LineCol { line: 0, col: 5 }), None);
+    }
+}
@@ -0,0 +1,26 @@
+// small grammars shared by the tests of the readers and parsers
+
+use reader::*;
+use reader::list_reader::ListReader;
+use reader::loop_reader::LoopOrdering;
+use reader::loop_reader::LoopReader;
+use reader::token_reader::TokenReader;
+use std::rc::Rc;
+use traces::Policy;
+
+pub const OPEN: TokenId = 0;
+pub const CLOSE: TokenId = 1;
+pub const A: TokenId = 2;
+
+pub fn token(id: TokenId) -> Rc<dyn Reader<TokenId>> {
+    rc_reader(TokenReader { token_ref: id, tag: None })
+}
+
+// `( a* )`
+pub fn grammar() -> Rc<dyn Reader<TokenId>> {
+    rc_reader(ListReader::new(vec![
+        token(OPEN),
+        rc_reader(LoopReader::new(token(A), Policy::Longest, LoopOrdering::Increasing, None)),
+        token(CLOSE),
+    ], None))
+}
@@ -1,8 +1,11 @@
 use list::*;
+use std::cmp::Ordering;
 use std::fmt::Debug;
 use std::iter::empty;
 use std::iter::once;
 use std::rc::Rc;
+use spans::Span;
+use spans::Spanned;
 use symbols::Tag;
 use traces::Trace;
 use traces::TraceEnding;
@@ -11,22 +14,31 @@ use trees::Tree::*;
 #[derive(Debug)]
 pub enum Tree<Tk> {
     Nil,
-    Leaf(Tk, Tag),
-    Node(Vec<Tree<Tk>>, Tag),
+    Leaf(Tk, Tag, Span),
+    Node(Vec<Tree<Tk>>, Tag, Span),
 }
 
 impl<Tk: 'static> Tree<Tk> {
     pub fn tag(&self) -> Tag {
         match *self {
             Nil => None,
-            Leaf(_, tag) => tag,
-            Node(_, tag) => tag,
+            Leaf(_, tag, _) => tag,
+            Node(_, tag, _) => tag,
+        }
+    }
+
+    // in bytes of the source, from the first leaf to the last one, see `tree_from_trace`
+    pub fn span(&self) -> Option<Span> {
+        match *self {
+            Nil => None,
+            Leaf(_, _, span) => Some(span),
+            Node(_, _, span) => Some(span),
         }
     }
 
     fn iter_on_children<'a>(&'a self, iter: impl FnMut(&'a Tree<Tk>) -> Box<dyn Iterator<Item=&'a Tree<Tk>> + 'a> + 'a) -> Box<dyn Iterator<Item=&'a Tree<Tk>> + 'a> {
         match *self {
-            Node(ref children, _) => Box::new(children.iter().flat_map(iter)),
+            Node(ref children, _, _) => Box::new(children.iter().flat_map(iter)),
             _ => Box::new(empty()),
         }
     }
@@ -88,14 +100,17 @@ impl<T: TreeBuilder> AsTreeBuilder for T {
     }
 }
 
-fn build_node<'a, 'b, Tk: Clone + Debug>(elts_with_traces: impl Iterator<Item=(&'a dyn TreeBuilder, &'a List<Trace, TraceEnding>)>, tokens: &'b [Tk], tag: Tag) -> (Tree<Tk>, &'b [Tk]) {
-    let (children, tokens) = elts_with_traces.fold((Vec::<Tree<Tk>>::new(), tokens),
-                                                   |(mut children, tokens), (builder, traces)| {
-                                                       let (tree, tokens) = build_rec(builder, traces, tokens);
-                                                       children.push(tree);
-                                                       (children, tokens)
-                                                   });
-    (Node(children, tag), tokens)
+// the byte span of the tokens from `start` to `stop`, empty ones being at the start of the next token
+type ByteSpan<'s> = &'s dyn Fn(usize, usize) -> Span;
+
+fn build_node<'a, 'b, Tk: Clone + Debug>(elts_with_traces: impl Iterator<Item=(&'a dyn TreeBuilder, &'a List<Trace, TraceEnding>)>, tokens: &'b [Tk], pos: usize, tag: Tag, bytes: ByteSpan) -> (Tree<Tk>, &'b [Tk], usize) {
+    let (children, tokens, stop) = elts_with_traces.fold((Vec::<Tree<Tk>>::new(), tokens, pos),
+                                                         |(mut children, tokens, pos), (builder, traces)| {
+                                                             let (tree, tokens, pos) = build_rec(builder, traces, tokens, pos, bytes);
+                                                             children.push(tree);
+                                                             (children, tokens, pos)
+                                                         });
+    (Node(children, tag, bytes(pos, stop)), tokens, stop)
 }
 
 fn as_rec_trace(trace: &Trace) -> &List<Trace, TraceEnding> {
@@ -106,12 +121,12 @@ fn as_rec_trace(trace: &Trace) -> &List<Trace, TraceEnding> {
     }
 }
 
-fn build_rec<'a, 'b, 'c, Tk: Clone + Debug>(builder: &'a dyn TreeBuilder, traces: &'b List<Trace, TraceEnding>, tokens: &'c [Tk]) -> (Tree<Tk>, &'c [Tk]) {
+fn build_rec<'a, 'b, 'c, Tk: Clone + Debug>(builder: &'a dyn TreeBuilder, traces: &'b List<Trace, TraceEnding>, tokens: &'c [Tk], pos: usize, bytes: ByteSpan) -> (Tree<Tk>, &'c [Tk], usize) {
     let add_branch = |next: &dyn TreeBuilder, traces: &List<Trace, TraceEnding>, tag: Tag| if tag.is_some() {
-        let (tree, tokens) = build_rec(next, traces, tokens);
-        (Tree::Node(vec![tree], tag), tokens)
+        let (tree, tokens, stop) = build_rec(next, traces, tokens, pos, bytes);
+        (Tree::Node(vec![tree], tag, bytes(pos, stop)), tokens, stop)
     } else {
-        build_rec(next, traces, tokens)
+        build_rec(next, traces, tokens, pos, bytes)
     };
     let volatile = builder.is_volatile();
     if let Some((next, tag)) = volatile {
@@ -119,28 +134,113 @@ fn build_rec<'a, 'b, 'c, Tk: Clone + Debug>(builder: &'a dyn TreeBuilder, traces
     }
     match traces {
         List::Nil(ending) => match ending {
-            TraceEnding::Token => (Leaf(tokens[0].clone(), builder.tag()), &tokens[1..]),
-            TraceEnding::Epsilon => (Tree::Nil, tokens),
+            TraceEnding::Token => (Leaf(tokens[0].clone(), builder.tag(), bytes(pos, pos + 1)), &tokens[1..], pos + 1),
+            TraceEnding::Epsilon => (Tree::Nil, tokens, pos),
             _ => unimplemented!(),
         },
         List::Cons(trace, tail) => match trace {
             Trace::Switch(index, _) => match builder.switch_builder(*index) {
                 SwitchBuilder::Case(next, tag) => add_branch(next, tail, tag),
-                SwitchBuilder::Loop => build_rec(builder, tail, tokens),
+                SwitchBuilder::Loop => build_rec(builder, tail, tokens, pos, bytes),
             },
             Trace::Rec(..) => {
                 let (elts, tag) = builder.node_builder();
-                build_node(elts.zip(traces.iter().map(as_rec_trace)), tokens, tag)
+                build_node(elts.zip(traces.iter().map(as_rec_trace)), tokens, pos, tag, bytes)
             },
             Trace::Tmp(tmp) => {
                 let (elts, tag) = builder.node_builder();
                 let rev_traces: Vec<&List<Trace, TraceEnding>> = tmp.iter().map(|t| t.as_ref()).collect();
-                build_node(elts.zip(rev_traces.iter().rev().map(|t| *t)), tokens, tag)
+                build_node(elts.zip(rev_traces.iter().rev().copied()), tokens, pos, tag, bytes)
             },
         },
     }
 }
 
-pub fn tree_from_trace<Tk: Clone + Debug>(builder: &dyn TreeBuilder, traces: &Rc<List<Trace, TraceEnding>>, tokens: &[Tk]) -> Tree<Tk> {
-    build_rec(builder, &traces, tokens).0
-}
\ No newline at end of file
+// the element traces of a node trace, first element first
+fn element_traces(traces: &List<Trace, TraceEnding>) -> Vec<&List<Trace, TraceEnding>> {
+    match traces {
+        List::Cons(Trace::Tmp(tmp), _) => {
+            let mut rev_traces: Vec<&List<Trace, TraceEnding>> = tmp.iter().map(|t| t.as_ref()).collect();
+            rev_traces.reverse();
+            rev_traces
+        }
+        List::Cons(Trace::Rec(..), _) => traces.iter().map(as_rec_trace).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn cmp_elements(builder: &dyn TreeBuilder, traces1: &List<Trace, TraceEnding>, traces2: &List<Trace, TraceEnding>) -> Ordering {
+    let (elts, _) = builder.node_builder();
+    elts.zip(element_traces(traces1).into_iter().zip(element_traces(traces2)))
+        .map(|(elt, (elt_traces1, elt_traces2))| cmp_readings(elt, elt_traces1, elt_traces2))
+        .find(|ordering| *ordering != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+// the order in which a backtracking matcher tries two readings by `builder`: the earlier case of a
+// switch first, and the loop reading more iterations first, from the first iteration they differ
+pub fn cmp_readings(builder: &dyn TreeBuilder, traces1: &List<Trace, TraceEnding>, traces2: &List<Trace, TraceEnding>) -> Ordering {
+    if let Some((next, _)) = builder.is_volatile() {
+        return cmp_readings(next, traces1, traces2);
+    }
+    match (traces1, traces2) {
+        (List::Cons(Trace::Switch(index1, _), tail1), List::Cons(Trace::Switch(index2, _), tail2)) => match builder.switch_builder(*index1) {
+            SwitchBuilder::Case(next, _) => if index1 == index2 { cmp_readings(next, tail1, tail2) } else { index1.cmp(index2) },
+            // the index of a loop is its number of iterations, negated when it reads them decreasing
+            SwitchBuilder::Loop => cmp_elements(builder, tail1, tail2).then((*index2 as isize).cmp(&(*index1 as isize))),
+        },
+        (List::Cons(..), List::Cons(..)) => cmp_elements(builder, traces1, traces2),
+        _ => Ordering::Equal,
+    }
+}
+
+// node spans are computed once, from the spans of the tokens
+pub fn tree_from_trace<Tk: Clone + Debug + Spanned>(builder: &dyn TreeBuilder, traces: &Rc<List<Trace, TraceEnding>>, tokens: &[Tk]) -> Tree<Tk> {
+    let bytes = |start: usize, stop: usize| if start < stop {
+        tokens[start].span().cover(tokens[stop - 1].span())
+    } else {
+        let at = tokens.get(start).map_or_else(|| tokens.last().map_or(0, |last| last.span().stop), |next| next.span().start);
+        Span::new(at, at)
+    };
+    build_rec(builder, traces, tokens, 0, &bytes).0
+}
+
+// the tokens are the bytes of the source from `offset`
+pub fn tree_from_trace_at<Tk: Clone + Debug>(builder: &dyn TreeBuilder, traces: &Rc<List<Trace, TraceEnding>>, tokens: &[Tk], offset: usize) -> Tree<Tk> {
+    build_rec(builder, traces, tokens, 0, &|start, stop| Span::new(offset + start, offset + stop)).0
+}
+
+#[cfg(test)]
+mod tests {
+    use json::parse_json;
+    use super::*;
+    use symbols::SymbolTable;
+
+    // the span of the first node tagged `tag`, depth first
+    fn tagged_span<Tk: 'static>(tree: &Tree<Tk>, tag: Tag) -> Option<Span> {
+        if tree.tag() == tag { return tree.span(); }
+        match *tree {
+            Node(ref children, _, _) => children.iter().filter_map(|child| tagged_span(child, tag)).next(),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn node_spans_are_byte_spans() {
+        let mut table = SymbolTable::new();
+        let src = " [1, {\"a\": 22}] ".to_string();
+        let (_, tree) = parse_json(&src, &mut table).unwrap();
+        assert_eq!(tree.span(), Some(Span::new(1, 15)));
+        assert_eq!(tagged_span(&tree, Some(table.get("obj"))), Some(Span::new(5, 14)));
+        assert_eq!(tagged_span(&tree, Some(table.get("pair"))), Some(Span::new(6, 13)));
+    }
+
+    #[test]
+    fn leaf_spans_are_token_spans() {
+        let mut table = SymbolTable::new();
+        let src = "[true,  null]".to_string();
+        let (_, tree) = parse_json(&src, &mut table).unwrap();
+        let spans: Vec<Span> = tree.leaves().filter_map(|leaf| leaf.span()).collect();
+        assert_eq!(spans, vec![Span::new(0, 1), Span::new(1, 5), Span::new(5, 6), Span::new(8, 12), Span::new(12, 13)]);
+    }
+}